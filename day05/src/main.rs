@@ -1,4 +1,5 @@
 use std::default::Default;
+use std::fmt;
 use std::fs::File;
 use std::str::FromStr;
 use std::io::{self, prelude::*, BufReader};
@@ -48,8 +49,26 @@ pub struct GridLine {
     y : [i32; 2],
 }
 
-#[derive(Debug)]
-pub struct ParseError;
+/// Why a line couldn't be parsed as a `GridLine`, along with the offending text.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A coordinate that isn't an integer.
+    BadCoordinate(String),
+    /// A point that isn't of the form "x,y".
+    BadPoint(String),
+    /// A line that doesn't have exactly two points separated by " -> ".
+    BadPointCount(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::BadCoordinate(s) => write!(f, "bad coordinate '{}'", s),
+            ParseError::BadPoint(s) => write!(f, "bad point '{}', expected 'x,y'", s),
+            ParseError::BadPointCount(s) => write!(f, "bad line '{}', expected two points", s),
+        }
+    }
+}
 
 impl FromStr for GridLine {
     type Err = ParseError;
@@ -57,21 +76,31 @@ impl FromStr for GridLine {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut gridline : GridLine = Default::default();
 
-        let mut i : usize = 0;
-        for point_str in s.split(" -> ") {
-            let coords : Vec<i32> = point_str.split(",").map(|x| x.parse::<i32>().unwrap()).collect();
+        let points : Vec<&str> = s.split(" -> ").collect();
+        if points.len() != 2 {
+            return Err(ParseError::BadPointCount(s.to_string()));
+        }
+        for (i, point_str) in points.iter().enumerate() {
+            let coords : Vec<&str> = point_str.split(',').collect();
             if coords.len() != 2 {
-                println!("Bad coord len");
-                return Err(Self::Err{})
+                return Err(ParseError::BadPoint(point_str.to_string()));
             }
-            gridline.x[i] = coords[0];
-            gridline.y[i] = coords[1];
-            i += 1;
+            let parse_coord = |c: &str| c.trim().parse::<i32>()
+                .map_err(|_| ParseError::BadCoordinate(c.to_string()));
+            gridline.x[i] = parse_coord(coords[0])?;
+            gridline.y[i] = parse_coord(coords[1])?;
         }
         Ok(gridline)
     }
 }
 
+// Writes the line back out in the same "x1,y1 -> x2,y2" form that we parse.
+impl fmt::Display for GridLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{} -> {},{}", self.x[0], self.y[0], self.x[1], self.y[1])
+    }
+}
+
 fn read_input_file(filename : &str) -> Vec<GridLine> {
     let file = File::open(filename).unwrap();
     let reader = BufReader::new(file);
@@ -113,4 +142,21 @@ mod test {
         assert_eq!(grid_hv.count_atleast(2), 5);
         assert_eq!(grid_hvd.count_atleast(2), 12);
     }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("1,2 -> 3,x".parse::<GridLine>(), Err(ParseError::BadCoordinate("x".to_string())));
+        assert_eq!("1,2".parse::<GridLine>(), Err(ParseError::BadPointCount("1,2".to_string())));
+        assert_eq!("1,2 -> 3,4 -> 5,6".parse::<GridLine>(),
+                   Err(ParseError::BadPointCount("1,2 -> 3,4 -> 5,6".to_string())));
+        assert_eq!("1,2 -> 3,4,5".parse::<GridLine>(), Err(ParseError::BadPoint("3,4,5".to_string())));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for line in std::fs::read_to_string("example_input.txt").unwrap().lines() {
+            let gridline : GridLine = line.parse().unwrap();
+            assert_eq!(gridline.to_string(), line.trim());
+        }
+    }
 }
\ No newline at end of file
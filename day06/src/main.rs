@@ -1,49 +1,801 @@
+use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, prelude::*, BufReader};
 use std::collections::VecDeque;
+use num_bigint::BigUint;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rand_distr::{Binomial, Distribution};
 
 const MATURE_PERIOD : usize = 2;
 const CYCLE_PERIOD : usize = 7;
 
-fn read_input_file(filename : &str) -> VecDeque<u64> {
+/// The fish grow exponentially, so a u64 runs out not long after day 256.  Everything that
+/// counts fish is generic over this, so the caller picks between fixed width integers (which
+/// report overflow rather than wrapping) and an arbitrary-precision BigUint.
+pub trait FishCount: Clone + PartialEq + fmt::Debug + fmt::Display {
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn from_u64(n: u64) -> Self;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+}
+
+macro_rules! impl_fish_count_for_primitive {
+    ($($t:ty),*) => {$(
+        impl FishCount for $t {
+            fn zero() -> Self { 0 }
+            fn one() -> Self { 1 }
+            fn from_u64(n: u64) -> Self { n.into() }
+            fn checked_add(&self, other: &Self) -> Option<Self> { <$t>::checked_add(*self, *other) }
+            fn checked_mul(&self, other: &Self) -> Option<Self> { <$t>::checked_mul(*self, *other) }
+        }
+    )*}
+}
+impl_fish_count_for_primitive!(u64, u128);
+
+impl FishCount for BigUint {
+    fn zero() -> Self { BigUint::from(0u32) }
+    fn one() -> Self { BigUint::from(1u32) }
+    fn from_u64(n: u64) -> Self { BigUint::from(n) }
+    fn checked_add(&self, other: &Self) -> Option<Self> { Some(self + other) }
+    fn checked_mul(&self, other: &Self) -> Option<Self> { Some(self * other) }
+}
+
+/// There were more fish than the chosen FishCount can hold.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CountOverflow;
+
+impl fmt::Display for CountOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fish count overflowed, try a wider count type")
+    }
+}
+
+impl From<CountOverflow> for io::Error {
+    fn from(err: CountOverflow) -> io::Error {
+        io::Error::other(err.to_string())
+    }
+}
+
+fn sum_counts<'a, C: FishCount + 'a>(counts: impl IntoIterator<Item = &'a C>) -> Result<C, CountOverflow> {
+    counts.into_iter().try_fold(C::zero(), |total, count| total.checked_add(count).ok_or(CountOverflow))
+}
+
+/// A lifecycle configuration file or flag we couldn't make sense of.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How a species of fish lives.  Fish are tracked by a timer counting down to their next spawn;
+/// when it passes zero they have `offspring` babies, and reset to `cycle_length - 1`.  Babies
+/// start at `cycle_length + maturity_delay - 1`.  If `max_spawns` is set, fish die once they've
+/// spawned that many times, which is how we express a maximum age.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lifecycle {
+    pub maturity_delay: usize,
+    pub cycle_length: usize,
+    pub offspring: u64,
+    pub max_spawns: Option<usize>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            maturity_delay: MATURE_PERIOD,
+            cycle_length: CYCLE_PERIOD,
+            offspring: 1,
+            max_spawns: None,
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Number of distinct timer values a fish can have.
+    pub fn num_timers(&self) -> usize {
+        self.cycle_length + self.maturity_delay
+    }
+
+    /// Length of a distribution - one set of timers per number of spawns so far if fish die,
+    /// otherwise just the one set, indexed by timer as in the original puzzle.
+    pub fn num_states(&self) -> usize {
+        self.num_timers() * self.max_spawns.unwrap_or(1)
+    }
+
+    /// The state every newborn fish starts in.
+    pub fn newborn_state(&self) -> usize {
+        self.num_timers() - 1
+    }
+
+    /// Every transition between states in one day.
+    pub fn transitions(&self) -> Vec<Transition> {
+        let timers = self.num_timers();
+        let groups = self.max_spawns.unwrap_or(1);
+        let mut transitions = Vec::new();
+        for group in 0..groups {
+            let base = group * timers;
+            for timer in 1..timers {
+                transitions.push(Transition { from: base + timer, to: base + timer - 1, multiplier: 1, is_birth: false });
+            }
+            // The parent starts its cycle again (in the next group if it can die)...
+            let next_group = if self.max_spawns.is_some() { group + 1 } else { group };
+            if next_group < groups {
+                transitions.push(Transition { from: base, to: next_group * timers + self.cycle_length - 1, multiplier: 1, is_birth: false });
+            }
+            // ...and the new fishies start their longer first cycle
+            transitions.push(Transition { from: base, to: self.newborn_state(), multiplier: self.offspring, is_birth: true });
+        }
+        transitions
+    }
+
+    /// A name for each state, for headers in exported forecasts.
+    pub fn state_name(&self, state: usize) -> String {
+        let timer = state % self.num_timers();
+        match self.max_spawns {
+            None => format!("timer_{}", timer),
+            Some(_) => format!("spawned_{}_timer_{}", state / self.num_timers(), timer),
+        }
+    }
+
+    /// The asymptotic factor the population grows by each day - the dominant eigenvalue of the
+    /// transition matrix.  Found by power iteration on (A + I), which has the same dominant
+    /// eigenvector but can't oscillate when every cycle length shares a common factor.
+    pub fn growth_rate(&self) -> f64 {
+        let transitions = self.transitions();
+        let mut vector = vec![1.0 / self.num_states() as f64; self.num_states()];
+        let mut rate = 0.0;
+        for _ in 0..100_000 {
+            let mut next = vector.clone();
+            for t in &transitions {
+                next[t.to] += vector[t.from] * t.multiplier as f64;
+            }
+            let total: f64 = next.iter().sum();
+            let new_rate = total - 1.0;
+            next.iter_mut().for_each(|x| *x /= total);
+            vector = next;
+            if (new_rate - rate).abs() < 1e-14 { return new_rate; }
+            rate = new_rate;
+        }
+        rate
+    }
+
+    /// Days for the population to double at the asymptotic growth rate, or None if it isn't growing.
+    pub fn doubling_time(&self) -> Option<f64> {
+        let rate = self.growth_rate();
+        if rate > 1.0 { Some(2f64.ln() / rate.ln()) } else { None }
+    }
+
+    /// Set a single parameter by name, as used in config files and command line flags.  On an
+    /// error the lifecycle is left as it was.  max-spawns can be "none" (or "unlimited") for
+    /// fish that never die.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let number = || value.trim().parse::<usize>()
+            .map_err(|_| ConfigError(format!("bad value '{}' for '{}'", value, key)));
+        let mut updated = self.clone();
+        match key.trim() {
+            "mature" => updated.maturity_delay = number()?,
+            "cycle" => updated.cycle_length = number()?,
+            "offspring" => updated.offspring = number()? as u64,
+            "max-spawns" => updated.max_spawns = match value.trim() {
+                "none" | "unlimited" => None,
+                _ => Some(number()?),
+            },
+            other => return Err(ConfigError(format!("unknown lifecycle parameter '{}'", other))),
+        }
+        if updated.cycle_length == 0 || updated.max_spawns == Some(0) {
+            return Err(ConfigError(format!("'{}' must be at least 1", key)));
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    /// Apply a config file of "key = value" lines, ignoring blank lines and # comments.
+    pub fn apply_config(&mut self, config: &str) -> Result<(), ConfigError> {
+        for line in config.lines().map(|x| x.trim()).filter(|x| !x.is_empty() && !x.starts_with('#')) {
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| ConfigError(format!("expected 'key = value', got '{}'", line)))?;
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// `multiplier` fish in state `to` for every fish in state `from`, one day later.  Births are
+/// marked, since with no maturity delay a newborn starts in the same state its parent resets to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub from: usize,
+    pub to: usize,
+    pub multiplier: u64,
+    pub is_birth: bool,
+}
+
+type Matrix<C> = Vec<Vec<C>>;
+
+fn identity_matrix<C: FishCount>(n: usize) -> Matrix<C> {
+    (0..n).map(|i| (0..n).map(|j| if i == j { C::one() } else { C::zero() }).collect()).collect()
+}
+
+fn matrix_multiply<C: FishCount>(a: &Matrix<C>, b: &Matrix<C>) -> Result<Matrix<C>, CountOverflow> {
+    let n = a.len();
+    let mut product = vec![vec![C::zero(); n]; n];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (k, b_row) in b.iter().enumerate() {
+            if a[i][k] == C::zero() { continue; }
+            for (j, cell) in row.iter_mut().enumerate() {
+                let term = a[i][k].checked_mul(&b_row[j]).ok_or(CountOverflow)?;
+                *cell = cell.checked_add(&term).ok_or(CountOverflow)?;
+            }
+        }
+    }
+    Ok(product)
+}
+
+/// The population as a linear system - each day multiplies the distribution of timers by a
+/// fixed transition matrix, so day N is the Nth power of that matrix applied to the start.
+/// Exponentiation by squaring gets us there in O(log N) matrix multiplies rather than N steps.
+pub struct LanternfishModel<C: FishCount> {
+    transition: Matrix<C>
+}
+
+impl<C: FishCount> LanternfishModel<C> {
+    /// Build the transition matrix for a Lifecycle.  Entry [to][from] is how many fish in
+    /// state 'to' one fish in state 'from' becomes the next day.
+    pub fn new(lifecycle: &Lifecycle) -> Self {
+        let n = lifecycle.num_states();
+        let mut transition = vec![vec![C::zero(); n]; n];
+        // Parents and babies can land in the same state, so add rather than overwrite
+        for t in lifecycle.transitions() {
+            transition[t.to][t.from] = transition[t.to][t.from].checked_add(&C::from_u64(t.multiplier))
+                .expect("Transition multipliers should fit in any count type");
+        }
+        LanternfishModel { transition }
+    }
+
+    /// Compute the distribution of timers `days` days after `fish_dist`.
+    pub fn distribution_at(&self, fish_dist: &VecDeque<C>, days: u64) -> Result<VecDeque<C>, CountOverflow> {
+        assert_eq!(fish_dist.len(), self.transition.len());
+        let mut power = identity_matrix(self.transition.len());
+        let mut square = self.transition.clone();
+        let mut remaining = days;
+        while remaining > 0 {
+            if remaining & 1 == 1 { power = matrix_multiply(&power, &square)?; }
+            remaining >>= 1;
+            if remaining > 0 { square = matrix_multiply(&square, &square)?; }
+        }
+        power.iter().map(|row| {
+            row.iter().zip(fish_dist).try_fold(C::zero(), |total, (m, count)| {
+                total.checked_add(&m.checked_mul(count).ok_or(CountOverflow)?).ok_or(CountOverflow)
+            })
+        }).collect()
+    }
+}
+
+/// Summary of the population on one day across many stochastic trials.
+#[derive(Debug, PartialEq)]
+pub struct DayStats {
+    pub mean: f64,
+    pub variance: f64,
+    pub p5: u64,
+    pub median: u64,
+    pub p95: u64,
+}
+
+impl DayStats {
+    fn from_populations(mut populations: Vec<u64>) -> Self {
+        populations.sort_unstable();
+        let n = populations.len() as f64;
+        let mean = populations.iter().map(|&x| x as f64).sum::<f64>() / n;
+        let variance = populations.iter().map(|&x| (x as f64 - mean).powi(2)).sum::<f64>() / n;
+        let percentile = |p: f64| populations[((populations.len() - 1) as f64 * p).round() as usize];
+        DayStats { mean, variance, p5: percentile(0.05), median: percentile(0.5), p95: percentile(0.95) }
+    }
+}
+
+/// The lifecycle with some luck involved - every fish survives each day with probability
+/// `1 - death_probability`, and each offspring it spawns is born with `birth_probability`.
+/// We draw binomially over each age bucket rather than rolling per fish, so large populations
+/// cost no more than small ones.  Births of 1 and deaths of 0 give back the deterministic `step`.
+pub struct StochasticModel {
+    lifecycle: Lifecycle,
+    birth_probability: f64,
+    death_probability: f64,
+}
+
+impl StochasticModel {
+    pub fn new(lifecycle: &Lifecycle, birth_probability: f64, death_probability: f64) -> Result<Self, ConfigError> {
+        for (name, probability) in [("birth", birth_probability), ("death", death_probability)] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(ConfigError(format!("{} probability {} must be in [0, 1]", name, probability)));
+            }
+        }
+        Ok(StochasticModel { lifecycle: lifecycle.clone(), birth_probability, death_probability })
+    }
+
+    /// Advance one day, drawing births and deaths from `rng`.
+    pub fn step<R: Rng>(&self, rng: &mut R, fish_dist: &mut VecDeque<u64>) -> Result<(), CountOverflow> {
+        assert_eq!(fish_dist.len(), self.lifecycle.num_states());
+        let survivors: Vec<u64> = fish_dist.iter().map(|&count| {
+            if self.death_probability == 0.0 { count } else { draw(rng, count, 1.0 - self.death_probability) }
+        }).collect();
+
+        let mut next = VecDeque::from(vec![0u64; fish_dist.len()]);
+        for t in self.lifecycle.transitions() {
+            let mut moved = survivors[t.from].checked_mul(t.multiplier).ok_or(CountOverflow)?;
+            if t.is_birth && self.birth_probability < 1.0 {
+                moved = draw(rng, moved, self.birth_probability);
+            }
+            next[t.to] = next[t.to].checked_add(moved).ok_or(CountOverflow)?;
+        }
+        *fish_dist = next;
+        Ok(())
+    }
+
+    /// Run `trials` independent simulations of `days` days from `initial`, seeded with `seed`,
+    /// and summarise the population on each day (index 0 is the initial population).
+    pub fn run_trials(&self, initial: &VecDeque<u64>, days: usize, trials: usize, seed: u64)
+        -> Result<Vec<DayStats>, CountOverflow>
+    {
+        assert!(trials > 0, "Need at least one trial");
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut populations = vec![Vec::with_capacity(trials); days + 1];
+        for _ in 0..trials {
+            let mut fish_dist = initial.clone();
+            populations[0].push(sum_counts(&fish_dist)?);
+            for population in populations.iter_mut().skip(1) {
+                self.step(&mut rng, &mut fish_dist)?;
+                population.push(sum_counts(&fish_dist)?);
+            }
+        }
+        Ok(populations.into_iter().map(DayStats::from_populations).collect())
+    }
+}
+
+/// One row per day of stochastic trial statistics, for charting like a Forecast.
+pub fn write_stats_csv<W: Write>(stats: &[DayStats], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "day,mean,stddev,p5,median,p95")?;
+    for (day, s) in stats.iter().enumerate() {
+        writeln!(writer, "{},{},{},{},{},{}", day, s.mean, s.variance.sqrt(), s.p5, s.median, s.p95)?;
+    }
+    Ok(())
+}
+
+/// The same statistics as `write_stats_csv`, as a JSON list of days.
+pub fn write_stats_json<W: Write>(stats: &[DayStats], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"days\": [")?;
+    for (day, s) in stats.iter().enumerate() {
+        let separator = if day + 1 < stats.len() { "," } else { "" };
+        writeln!(writer, "    {{\"day\": {}, \"mean\": {}, \"stddev\": {}, \"p5\": {}, \"median\": {}, \"p95\": {}}}{}",
+            day, s.mean, s.variance.sqrt(), s.p5, s.median, s.p95, separator)?;
+    }
+    writeln!(writer, "  ]")?;
+    writeln!(writer, "}}")
+}
+
+/// How many of `n` fish a Binomial(n, p) draw says made it.
+fn draw<R: Rng>(rng: &mut R, n: u64, p: f64) -> u64 {
+    if n == 0 { return 0; }
+    Binomial::new(n, p).unwrap().sample(rng)
+}
+
+/// The full distribution of states for each day of a simulation, for charting.
+pub struct Forecast<'a, C: FishCount> {
+    lifecycle: &'a Lifecycle,
+    distributions: Vec<VecDeque<C>>,
+}
+
+impl<'a, C: FishCount> Forecast<'a, C> {
+    /// One row per day, with the total then the count in every state.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let names: Vec<String> = (0..self.lifecycle.num_states()).map(|i| self.lifecycle.state_name(i)).collect();
+        writeln!(writer, "day,total,{}", names.join(","))?;
+        for (day, fish_dist) in self.distributions.iter().enumerate() {
+            let counts: Vec<String> = fish_dist.iter().map(|x| x.to_string()).collect();
+            writeln!(writer, "{},{},{}", day, self.total(fish_dist)?, counts.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// The lifecycle, its growth analysis and every day's distribution, so that forecasts from
+    /// different lifecycles can be compared side by side.
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let lifecycle = self.lifecycle;
+        let optional = |x: Option<String>| x.unwrap_or_else(|| "null".to_string());
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"lifecycle\": {{\"maturity_delay\": {}, \"cycle_length\": {}, \"offspring\": {}, \"max_spawns\": {}}},",
+            lifecycle.maturity_delay, lifecycle.cycle_length, lifecycle.offspring,
+            optional(lifecycle.max_spawns.map(|x| x.to_string())))?;
+        writeln!(writer, "  \"growth_rate\": {},", lifecycle.growth_rate())?;
+        writeln!(writer, "  \"doubling_time\": {},", optional(lifecycle.doubling_time().map(|x| x.to_string())))?;
+        let names: Vec<String> = (0..lifecycle.num_states()).map(|i| format!("\"{}\"", lifecycle.state_name(i))).collect();
+        writeln!(writer, "  \"states\": [{}],", names.join(", "))?;
+        writeln!(writer, "  \"days\": [")?;
+        for (day, fish_dist) in self.distributions.iter().enumerate() {
+            let counts: Vec<String> = fish_dist.iter().map(|x| x.to_string()).collect();
+            let separator = if day + 1 < self.distributions.len() { "," } else { "" };
+            writeln!(writer, "    {{\"day\": {}, \"total\": {}, \"distribution\": [{}]}}{}",
+                day, self.total(fish_dist)?, counts.join(", "), separator)?;
+        }
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")
+    }
+
+    fn total(&self, fish_dist: &VecDeque<C>) -> io::Result<C> {
+        sum_counts(fish_dist).map_err(|err| io::Error::other(err.to_string()))
+    }
+}
+
+/// Read the initial timers, all of which are fish that haven't spawned yet.
+fn read_input_file<C: FishCount>(filename : &str, lifecycle: &Lifecycle) -> VecDeque<C> {
     let file = File::open(filename).unwrap();
     let reader = BufReader::new(file);
-    let mut fish_dist = VecDeque::<u64>::from([0u64; CYCLE_PERIOD + MATURE_PERIOD]);
+    let mut fish_dist = VecDeque::from(vec![C::zero(); lifecycle.num_states()]);
     let line = reader.lines().next().unwrap().unwrap();
-    for age in line.trim().split(',').map(|x| x.parse::<u64>().unwrap()) {
-        fish_dist[age.try_into().unwrap()] += 1;
+    for age in line.trim().split(',').map(|x| x.parse::<usize>().unwrap()) {
+        assert!(age < lifecycle.num_timers(), "Timer {} is too long for this lifecycle", age);
+        fish_dist[age] = fish_dist[age].checked_add(&C::one()).unwrap();
     }
     fish_dist
 }
 
-fn step(fish_dist: &mut VecDeque<u64>) {
-    let gave_birth = fish_dist.pop_front().unwrap();
-    fish_dist.push_back(gave_birth); // These are all the new fishies
-    fish_dist[CYCLE_PERIOD-1] += gave_birth; // These are the original fishies
-    assert_eq!(fish_dist.len(), CYCLE_PERIOD + MATURE_PERIOD);
+fn step<C: FishCount>(lifecycle: &Lifecycle, fish_dist: &mut VecDeque<C>) -> Result<(), CountOverflow> {
+    assert_eq!(fish_dist.len(), lifecycle.num_states());
+    let mut next = VecDeque::from(vec![C::zero(); fish_dist.len()]);
+    for t in lifecycle.transitions() {
+        let moved = fish_dist[t.from].checked_mul(&C::from_u64(t.multiplier)).ok_or(CountOverflow)?;
+        next[t.to] = next[t.to].checked_add(&moved).ok_or(CountOverflow)?;
+    }
+    *fish_dist = next;
+    Ok(())
 }
 
-fn main() -> io::Result<()> {
-    let mut fish_dist = read_input_file("input.txt");
-    for day in 1..=256 {
-        step(&mut fish_dist);
-        let num_fish: u64 = fish_dist.iter().sum();
-        println!("Day {} has {} fish", day, num_fish);
+/// Past this many days we only print the projection rather than every day's count.
+const MAX_DAILY_DAYS: u64 = 10_000;
+
+fn simulate<C: FishCount>(lifecycle: &Lifecycle, days: u64, csv: Option<&str>, json: Option<&str>)
+    -> io::Result<()>
+{
+    let mut fish_dist = read_input_file::<C>("input.txt", lifecycle);
+    let initial = fish_dist.clone();
+    // Only keep every day's distribution around if something is going to export it.
+    let exporting = csv.is_some() || json.is_some();
+    let mut forecast = Forecast { lifecycle, distributions: Vec::new() };
+    if exporting {
+        forecast.distributions.push(initial.clone());
+    }
+    // Stepping a day at a time is linear in days, so leave long runs to the model unless
+    // an export needs every day anyway.
+    if days <= MAX_DAILY_DAYS || exporting {
+        for day in 1..=days {
+            let on_day = |err: CountOverflow| io::Error::other(format!("day {}: {}", day, err));
+            step(lifecycle, &mut fish_dist).map_err(on_day)?;
+            println!("Day {} has {} fish", day, sum_counts(&fish_dist).map_err(on_day)?);
+            if exporting {
+                forecast.distributions.push(fish_dist.clone());
+            }
+        }
     }
 
+    let projected = LanternfishModel::new(lifecycle).distribution_at(&initial, days)?;
+    println!("Day {} projected to have {} fish", days, sum_counts(&projected)?);
+    match lifecycle.doubling_time() {
+        Some(doubling) => println!("Growing by a factor of {:.6} per day, doubling every {:.3} days",
+            lifecycle.growth_rate(), doubling),
+        None => println!("Changing by a factor of {:.6} per day, so never doubling", lifecycle.growth_rate()),
+    }
+
+    if let Some(filename) = csv { export(filename, |file| forecast.write_csv(file))?; }
+    if let Some(filename) = json { export(filename, |file| forecast.write_json(file))?; }
+    Ok(())
+}
+
+/// Create `filename` and write to it, saying which file it was if that goes wrong.
+fn export<F: FnOnce(&mut File) -> io::Result<()>>(filename: &str, write: F) -> io::Result<()> {
+    File::create(filename).and_then(|mut file| write(&mut file))
+        .map_err(|err| io::Error::new(err.kind(), format!("couldn't write {}: {}", filename, err)))
+}
+
+fn simulate_stochastic(model: &StochasticModel, lifecycle: &Lifecycle, days: u64, trials: usize, seed: u64,
+    csv: Option<&str>, json: Option<&str>) -> io::Result<()>
+{
+    let initial = read_input_file::<u64>("input.txt", lifecycle);
+    let stats = model.run_trials(&initial, days as usize, trials, seed)?;
+    for (day, day_stats) in stats.iter().enumerate().skip(1) {
+        println!("Day {} has mean {:.1} (stddev {:.1}), 5%/50%/95% at {}/{}/{} fish",
+            day, day_stats.mean, day_stats.variance.sqrt(), day_stats.p5, day_stats.median, day_stats.p95);
+    }
+    if let Some(filename) = csv { export(filename, |file| write_stats_csv(&stats, file))?; }
+    if let Some(filename) = json { export(filename, |file| write_stats_json(&stats, file))?; }
     Ok(())
 }
 
+fn main() -> io::Result<()> {
+    // Usage: day6 [days] [u64|u128|big] [--config FILE] [--mature N] [--cycle N]
+    //             [--offspring N] [--max-spawns N|none]
+    //             [--trials N] [--birth-prob P] [--death-prob P] [--seed N]
+    //             [--csv FILE] [--json FILE]
+    // Passing --trials runs the stochastic simulation, which always counts with u64, and
+    // exports each day's statistics rather than its distribution.
+    // Runs longer than MAX_DAILY_DAYS only print the projected total, not every day.
+    let mut days : u64 = 256;
+    let mut count_type = "big".to_string();
+    let mut lifecycle = Lifecycle::default();
+    let mut trials : Option<usize> = None;
+    let (mut csv, mut json) : (Option<String>, Option<String>) = (None, None);
+    let (mut birth_probability, mut death_probability, mut seed) = (1.0, 0.0, 0u64);
+    let mut positional = 0;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(flag) = arg.strip_prefix("--") {
+            let value = args.next().unwrap_or_else(|| panic!("--{} needs a value", flag));
+            let bad_value = || ConfigError(format!("bad value '{}' for --{}", value, flag));
+            let result = match flag {
+                "config" => lifecycle.apply_config(&std::fs::read_to_string(&value)?),
+                "trials" => value.parse().ok().filter(|&t| t > 0).map(|t| trials = Some(t)).ok_or_else(bad_value),
+                "birth-prob" => value.parse().map(|p| birth_probability = p).map_err(|_| bad_value()),
+                "death-prob" => value.parse().map(|p| death_probability = p).map_err(|_| bad_value()),
+                "seed" => value.parse().map(|n| seed = n).map_err(|_| bad_value()),
+                "csv" => { csv = Some(value); Ok(()) },
+                "json" => { json = Some(value); Ok(()) },
+                _ => lifecycle.set(flag, &value),
+            };
+            result.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        } else {
+            match positional {
+                0 => days = arg.parse().expect("Days must be a number"),
+                _ => count_type = arg,
+            }
+            positional += 1;
+        }
+    }
+
+    if let Some(trials) = trials {
+        let model = StochasticModel::new(&lifecycle, birth_probability, death_probability)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        return simulate_stochastic(&model, &lifecycle, days, trials, seed, csv.as_deref(), json.as_deref());
+    }
+
+    match count_type.as_str() {
+        "u64" => simulate::<u64>(&lifecycle, days, csv.as_deref(), json.as_deref()),
+        "u128" => simulate::<u128>(&lifecycle, days, csv.as_deref(), json.as_deref()),
+        "big" => simulate::<BigUint>(&lifecycle, days, csv.as_deref(), json.as_deref()),
+        other => panic!("Unknown count type '{}', expected u64, u128 or big", other),
+    }
+}
+
 
 #[cfg(test)]
 mod test {
     use super::*;
     #[test]
     fn test_example() {
-        let mut fish_dist = VecDeque::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
-        step(&mut fish_dist);
+        let lifecycle = Lifecycle::default();
+        let mut fish_dist = VecDeque::<u64>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        step(&lifecycle, &mut fish_dist).unwrap();
         assert_eq!(fish_dist, VecDeque::from([1, 1, 2, 1, 0, 0, 0, 0, 0]));
-        step(&mut fish_dist);
+        step(&lifecycle, &mut fish_dist).unwrap();
         assert_eq!(fish_dist, VecDeque::from([1, 2, 1, 0, 0, 0, 1, 0, 1]));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_model_matches_step() {
+        let lifecycle = Lifecycle::default();
+        let model = LanternfishModel::new(&lifecycle);
+        let initial = VecDeque::<u64>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        let mut fish_dist = initial.clone();
+        for day in 0..=200 {
+            assert_eq!(model.distribution_at(&initial, day).unwrap(), fish_dist);
+            step(&lifecycle, &mut fish_dist).unwrap();
+        }
+
+        let other = VecDeque::<u64>::from([3, 0, 7, 1, 0, 2, 5, 4, 9]);
+        let mut fish_dist = other.clone();
+        for _ in 0..137 { step(&lifecycle, &mut fish_dist).unwrap(); }
+        assert_eq!(model.distribution_at(&other, 137).unwrap(), fish_dist);
+    }
+
+    #[test]
+    fn test_model_example() {
+        let lifecycle = Lifecycle::default();
+        let model = LanternfishModel::new(&lifecycle);
+        let initial = VecDeque::<u64>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        let total = |days| sum_counts(&model.distribution_at(&initial, days).unwrap()).unwrap();
+        assert_eq!(total(18), 26);
+        assert_eq!(total(80), 5934);
+        assert_eq!(total(256), 26984457539);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let lifecycle = Lifecycle::default();
+        let initial = VecDeque::<u64>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        let mut fish_dist = initial.clone();
+        let overflowed = (0..1000).map(|_| step(&lifecycle, &mut fish_dist)).find(|r| r.is_err());
+        assert_eq!(overflowed, Some(Err(CountOverflow)));
+        assert_eq!(LanternfishModel::new(&lifecycle).distribution_at(&initial, 1000), Err(CountOverflow));
+
+        // The wider types agree with each other for as long as u128 lasts, and BigUint keeps going
+        let wide = VecDeque::<u128>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        let big : VecDeque<BigUint> = wide.iter().map(|&x| BigUint::from(x)).collect();
+        let wide_total = sum_counts(&LanternfishModel::new(&lifecycle).distribution_at(&wide, 600).unwrap()).unwrap();
+        let big_total = sum_counts(&LanternfishModel::new(&lifecycle).distribution_at(&big, 600).unwrap()).unwrap();
+        assert_eq!(big_total, BigUint::from(wide_total));
+        assert_eq!(LanternfishModel::new(&lifecycle).distribution_at(&wide, 2000), Err(CountOverflow));
+        assert!(LanternfishModel::new(&lifecycle).distribution_at(&big, 2000).is_ok());
+    }
+
+    #[test]
+    fn test_lifecycle_model_matches_step() {
+        let mut lifecycle = Lifecycle::default();
+        lifecycle.apply_config("# A hardier species\nmature = 3\ncycle = 5\n\noffspring = 2\nmax-spawns = 3\n").unwrap();
+        assert_eq!(lifecycle, Lifecycle { maturity_delay: 3, cycle_length: 5, offspring: 2, max_spawns: Some(3) });
+        assert_eq!(lifecycle.num_states(), 24);
+
+        let model = LanternfishModel::new(&lifecycle);
+        let mut initial = VecDeque::<u64>::from(vec![0; 24]);
+        initial[1] = 2;
+        initial[4] = 1;
+        let mut fish_dist = initial.clone();
+        for day in 0..=60 {
+            assert_eq!(model.distribution_at(&initial, day).unwrap(), fish_dist);
+            step(&lifecycle, &mut fish_dist).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_lifecycle_death() {
+        // A fish that spawns once then dies just replaces itself
+        let lifecycle = Lifecycle { maturity_delay: 0, cycle_length: 3, offspring: 1, max_spawns: Some(1) };
+        let mut fish_dist = VecDeque::<u64>::from([1, 0, 0]);
+        for _ in 0..10 {
+            step(&lifecycle, &mut fish_dist).unwrap();
+            assert_eq!(sum_counts(&fish_dist).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_lifecycle_config_errors() {
+        let mut lifecycle = Lifecycle::default();
+        assert!(lifecycle.set("cycle", "seven").is_err());
+        assert!(lifecycle.set("cycle", "0").is_err());
+        assert!(lifecycle.set("max-spawns", "0").is_err());
+        assert_eq!(lifecycle, Lifecycle::default());
+        assert!(lifecycle.set("lifespan", "3").is_err());
+
+        lifecycle.set("max-spawns", "3").unwrap();
+        assert_eq!(lifecycle.max_spawns, Some(3));
+        lifecycle.set("max-spawns", "none").unwrap();
+        assert_eq!(lifecycle.max_spawns, None);
+        assert!(lifecycle.apply_config("cycle: 7").is_err());
+    }
+
+    #[test]
+    fn test_stochastic_deterministic_case() {
+        let mut lifecycle = Lifecycle::default();
+        let model = StochasticModel::new(&lifecycle, 1.0, 0.0).unwrap();
+        let initial = VecDeque::<u64>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        let stats = model.run_trials(&initial, 80, 5, 42).unwrap();
+        assert_eq!(stats[18], DayStats { mean: 26.0, variance: 0.0, p5: 26, median: 26, p95: 26 });
+        assert_eq!(stats[80].median, 5934);
+
+        // And the same with a lifecycle where fish die, against the deterministic step
+        lifecycle.set("max-spawns", "2").unwrap();
+        lifecycle.set("offspring", "3").unwrap();
+        let model = StochasticModel::new(&lifecycle, 1.0, 0.0).unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut expected = VecDeque::<u64>::from(vec![0; lifecycle.num_states()]);
+        expected[3] = 4;
+        let mut fish_dist = expected.clone();
+        for _ in 0..50 {
+            step(&lifecycle, &mut expected).unwrap();
+            model.step(&mut rng, &mut fish_dist).unwrap();
+            assert_eq!(fish_dist, expected);
+        }
+    }
+
+    #[test]
+    fn test_no_maturity_delay() {
+        // Newborns start where their parents reset to, but only the babies are a birth
+        let lifecycle = Lifecycle { maturity_delay: 0, cycle_length: 3, offspring: 1, max_spawns: None };
+        let initial = VecDeque::<u64>::from([10, 0, 0]);
+        let model = StochasticModel::new(&lifecycle, 0.0, 0.0).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut fish_dist = initial.clone();
+        for _ in 0..3 { model.step(&mut rng, &mut fish_dist).unwrap(); }
+        assert_eq!(fish_dist, VecDeque::from([10, 0, 0]));
+
+        // And with every birth happening, step and the matrix model double the population
+        let mut fish_dist = initial.clone();
+        for _ in 0..3 { step(&lifecycle, &mut fish_dist).unwrap(); }
+        assert_eq!(fish_dist, VecDeque::from([20, 0, 0]));
+        assert_eq!(LanternfishModel::new(&lifecycle).distribution_at(&initial, 3).unwrap(), fish_dist);
+    }
+
+    #[test]
+    fn test_stochastic_trials() {
+        let lifecycle = Lifecycle::default();
+        let initial = VecDeque::<u64>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        let model = StochasticModel::new(&lifecycle, 0.5, 0.01).unwrap();
+        let stats = model.run_trials(&initial, 60, 200, 1234).unwrap();
+        // The same seed gives the same answer
+        assert_eq!(stats, model.run_trials(&initial, 60, 200, 1234).unwrap());
+        assert_eq!(stats[0].mean, 5.0);
+        let last = &stats[60];
+        assert!(last.variance > 0.0);
+        assert!(last.p5 <= last.median && last.median <= last.p95);
+        // Fewer births and some deaths can only do worse than the deterministic population
+        assert!(last.mean < 5934.0);
+
+        // Everybody dies on the first day
+        assert!(StochasticModel::new(&lifecycle, 2.0, 0.0).is_err());
+        assert!(StochasticModel::new(&lifecycle, 0.5, -0.1).is_err());
+        let doomed = StochasticModel::new(&lifecycle, 1.0, 1.0).unwrap().run_trials(&initial, 3, 10, 0).unwrap();
+        assert_eq!(doomed[1].mean, 0.0);
+
+        let mut csv = Vec::new();
+        write_stats_csv(&doomed[..2], &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "day,mean,stddev,p5,median,p95\n0,5,0,5,5,5\n1,0,0,0,0,0\n");
+        let mut json = Vec::new();
+        write_stats_json(&doomed, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("{\"day\": 3, \"mean\": 0, \"stddev\": 0, \"p5\": 0, \"median\": 0, \"p95\": 0}\n  ]"));
+    }
+
+    #[test]
+    fn test_growth_rate() {
+        // Compare against how the population changes over two days far into the future
+        let mut lifecycle = Lifecycle::default();
+        let ratio = |lifecycle: &Lifecycle| {
+            let mut initial = VecDeque::<BigUint>::from(vec![BigUint::from(0u32); lifecycle.num_states()]);
+            initial[0] = BigUint::from(1u32);
+            let model = LanternfishModel::new(lifecycle);
+            let later = sum_counts(&model.distribution_at(&initial, 2002).unwrap()).unwrap();
+            let earlier = sum_counts(&model.distribution_at(&initial, 2000).unwrap()).unwrap();
+            (later * BigUint::from(1_000_000_000u64) / earlier).to_string().parse::<f64>().unwrap().sqrt() / 1e9f64.sqrt()
+        };
+        assert!((lifecycle.growth_rate() - ratio(&lifecycle)).abs() < 1e-8);
+        assert!((lifecycle.doubling_time().unwrap() - 2f64.ln() / lifecycle.growth_rate().ln()).abs() < 1e-12);
+
+        // Cycles of 4 and 6 share a factor of two, so the population oscillates from day to day,
+        // which would also trip up plain power iteration
+        lifecycle.apply_config("cycle = 4\noffspring = 2").unwrap();
+        assert!((lifecycle.growth_rate() - ratio(&lifecycle)).abs() < 1e-8);
+
+        // Fish that only ever replace themselves don't grow
+        let steady = Lifecycle { maturity_delay: 2, cycle_length: 7, offspring: 1, max_spawns: Some(1) };
+        assert!((steady.growth_rate() - 1.0).abs() < 1e-9);
+        assert_eq!(steady.doubling_time(), None);
+    }
+
+    #[test]
+    fn test_forecast_export() {
+        let lifecycle = Lifecycle::default();
+        let mut fish_dist = VecDeque::<u64>::from([0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        let mut forecast = Forecast { lifecycle: &lifecycle, distributions: vec![fish_dist.clone()] };
+        for _ in 0..2 {
+            step(&lifecycle, &mut fish_dist).unwrap();
+            forecast.distributions.push(fish_dist.clone());
+        }
+
+        let mut csv = Vec::new();
+        forecast.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "\
+day,total,timer_0,timer_1,timer_2,timer_3,timer_4,timer_5,timer_6,timer_7,timer_8
+0,5,0,1,1,2,1,0,0,0,0
+1,5,1,1,2,1,0,0,0,0,0
+2,6,1,2,1,0,0,0,1,0,1
+");
+
+        let mut json = Vec::new();
+        forecast.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"max_spawns\": null"));
+        assert!(json.contains("{\"day\": 2, \"total\": 6, \"distribution\": [1, 2, 1, 0, 0, 0, 1, 0, 1]}\n  ]"));
+    }
+}
@@ -11,8 +11,90 @@ fn identity(dx: u64) -> u64 { dx }
 /// 1 + 2 + 3 + 4 + ... - each step away increases the cost by one.
 fn triangular_number(dx: u64) -> u64 { dx*(dx+1)/2 }
 
+/// How much fuel a crab burns to move a distance.  Any `Fn(u64) -> u64` is a FuelCost, so plain
+/// functions like `identity` and closures capturing parameters both work.  Fleets with several
+/// kinds of crab can give each kind its own cost by overriding `cost_for_kind`.
+pub trait FuelCost {
+    fn cost(&self, dx: u64) -> u64;
+
+    fn cost_for_kind(&self, _kind: usize, dx: u64) -> u64 { self.cost(dx) }
+}
+
+impl<F: Fn(u64) -> u64> FuelCost for F {
+    fn cost(&self, dx: u64) -> u64 { self(dx) }
+}
+
+/// Cost is a polynomial in the distance - coefficients[i] * dx^i.  Convex as long as the
+/// coefficients are (as they must be) non-negative.
+#[derive(Clone, Debug)]
+pub struct Polynomial {
+    pub coefficients: Vec<u64>
+}
+
+impl FuelCost for Polynomial {
+    fn cost(&self, dx: u64) -> u64 {
+        self.coefficients.iter().rev().fold(0, |total, c| total * dx + c)
+    }
+}
+
+/// Another cost, but never more than `cap` - say if a crab can hitch a ride for a flat fee.
+/// Note this is not convex, so needs the brute force search.
+#[derive(Clone, Debug)]
+pub struct Capped<C: FuelCost> {
+    pub inner: C,
+    pub cap: u64,
+}
+
+impl<C: FuelCost> FuelCost for Capped<C> {
+    fn cost(&self, dx: u64) -> u64 { min(self.inner.cost(dx), self.cap) }
+    fn cost_for_kind(&self, kind: usize, dx: u64) -> u64 { min(self.inner.cost_for_kind(kind, dx), self.cap) }
+}
+
+/// Each step costs a rate that depends on how far the crab has already come.  `rates` holds
+/// (from_step, rate) pairs sorted by from_step and starting at zero, e.g. [(0, 1), (10, 3)] is a
+/// crab that moves for 1 a step until it tires after 10 steps, then 3 a step after that.  Convex
+/// when the rates never go down.
+#[derive(Clone, Debug)]
+pub struct Piecewise {
+    pub rates: Vec<(u64, u64)>
+}
+
+impl FuelCost for Piecewise {
+    fn cost(&self, dx: u64) -> u64 {
+        let mut cost = 0;
+        for (i, &(from_step, rate)) in self.rates.iter().enumerate() {
+            if dx <= from_step { break; }
+            let to_step = self.rates.get(i + 1).map_or(dx, |&(next_from, _)| min(next_from, dx));
+            cost += (to_step - from_step) * rate;
+        }
+        cost
+    }
+}
+
+/// A different cost for each kind of crab, indexed by the kinds given to the CrabArmada.
+pub struct PerKind {
+    costs: Vec<Box<dyn FuelCost>>
+}
+
+impl PerKind {
+    /// The first cost doubles as the plain `cost`, so there has to be at least one.
+    pub fn new(costs: Vec<Box<dyn FuelCost>>) -> Self {
+        assert!(!costs.is_empty(), "PerKind needs a cost for at least one kind");
+        PerKind { costs }
+    }
+}
+
+impl FuelCost for PerKind {
+    fn cost(&self, dx: u64) -> u64 { self.costs[0].cost(dx) }
+    fn cost_for_kind(&self, kind: usize, dx: u64) -> u64 { self.costs[kind].cost(dx) }
+}
+
+/// Crabs at `positions`.  Each crab also has a weight (how many crabs are really at that spot,
+/// or how much we care about their fuel) and a kind, for fleets where crabs move differently.
 pub struct CrabArmada {
-    positions : Vec<u64>
+    positions : Vec<u64>,
+    weights : Vec<u64>,
+    kinds : Vec<usize>,
 }
 
 impl CrabArmada {
@@ -21,61 +103,546 @@ impl CrabArmada {
         let file = File::open(filename).unwrap();
         let reader = BufReader::new(file);
         let line = reader.lines().next().unwrap().unwrap();
-        CrabArmada {
-            positions: line.trim().split(',').map(|x| x.parse::<u64>().unwrap()).collect()
-        }
+        CrabArmada::from_vec(line.trim().split(',').map(|x| x.parse::<u64>().unwrap()).collect())
     }
 
-    /// Create a CrabArmada from a vector.
+    /// Create a CrabArmada from a vector, with every crab of weight 1 and kind 0.
     pub fn from_vec(positions : Vec<u64>) -> Self {
-        CrabArmada { positions }
+        let num_crabs = positions.len();
+        CrabArmada { positions, weights: vec![1; num_crabs], kinds: vec![0; num_crabs] }
+    }
+
+    /// Give each crab (in the same order as the positions) a weight.
+    pub fn with_weights(mut self, weights : Vec<u64>) -> Self {
+        assert_eq!(weights.len(), self.positions.len());
+        self.weights = weights;
+        self
+    }
+
+    /// Give each crab (in the same order as the positions) a kind, for `FuelCost::cost_for_kind`.
+    pub fn with_kinds(mut self, kinds : Vec<usize>) -> Self {
+        assert_eq!(kinds.len(), self.positions.len());
+        self.kinds = kinds;
+        self
     }
 
     /// Compute the cost of a given 'blast point', using a given cost function.  A 'best_cost' can
     /// be provided, which will allow the function to bail out early (returning None) if exceeded.
-    pub fn compute_blast_point_cost(&self,
+    pub fn compute_blast_point_cost<C: FuelCost + ?Sized>(&self,
         blast_point : u64,
         best_cost : Option<u64>,
-        cost_fn : fn(u64) -> u64
+        cost_fn : &C
     ) -> Option<u64> {
 
         let mut cost: u64 = 0;
-        for pos in &self.positions {
-            cost += cost_fn(abs_diff(blast_point, *pos));
+        for ((pos, weight), kind) in self.positions.iter().zip(&self.weights).zip(&self.kinds) {
+            cost += weight * cost_fn.cost_for_kind(*kind, abs_diff(blast_point, *pos));
             if best_cost.is_some() && cost >= best_cost.unwrap() { return None; }
         }
-        return Some(cost);
+        Some(cost)
     }
 
-    /// Find the minimal cost 'blast point', using a given cost function.
-    pub fn find_blast_point(&self, cost_fn : fn(u64) -> u64) -> (u64, u64) {
+    /// Find the minimal cost 'blast point', using a given cost function, by trying every point
+    /// between the outermost crabs.  The fast paths below should agree with this exactly,
+    /// including picking the lowest point when several share the minimum cost.
+    pub fn find_blast_point<C: FuelCost + ?Sized>(&self, cost_fn : &C) -> (u64, u64) {
         let minima = self.positions.iter().min().unwrap();
         let maxima = self.positions.iter().max().unwrap();
         let mut best_point : Option<u64> = None;
         let mut best_cost : Option<u64> = None;
 
         for blast_point in *minima..=*maxima {
-            match self.compute_blast_point_cost(blast_point, best_cost, cost_fn) {
-                Some(new_cost) => {
-                    best_cost = Some(new_cost);
-                    best_point = Some(blast_point);
-                },
-                None => { }
+            if let Some(new_cost) = self.compute_blast_point_cost(blast_point, best_cost, cost_fn) {
+                best_cost = Some(new_cost);
+                best_point = Some(blast_point);
             }
         }
 
         (best_point.unwrap(), best_cost.unwrap())
     }
+
+    /// The total cost of every candidate blast point between the outermost crabs, without the
+    /// early exit `find_blast_point` uses, so we can see the whole shape of the curve.
+    pub fn cost_curve<C: FuelCost + ?Sized>(&self, name : &str, cost_fn : &C) -> CostCurve {
+        let minima = *self.positions.iter().min().unwrap();
+        let maxima = *self.positions.iter().max().unwrap();
+        CostCurve {
+            name: name.to_string(),
+            costs: (minima..=maxima)
+                .map(|blast_point| (blast_point, self.compute_blast_point_cost(blast_point, None, cost_fn).unwrap()))
+                .collect()
+        }
+    }
+
+    /// Find the minimal cost 'blast point' for the `identity` cost.  Moving one step right costs
+    /// one for every crab to the left and saves one for every crab to the right, so the (lower,
+    /// weighted) median is the lowest point where that stops paying off.
+    pub fn find_blast_point_linear(&self) -> (u64, u64) {
+        let mut sorted : Vec<(u64, u64)> = self.positions.iter().copied().zip(self.weights.iter().copied()).collect();
+        sorted.sort_unstable();
+        let total_weight : u64 = self.weights.iter().sum();
+        let mut weight_so_far = 0;
+        let mut median = sorted[0].0;
+        for (pos, weight) in sorted {
+            weight_so_far += weight;
+            median = pos;
+            if 2 * weight_so_far >= total_weight { break; }
+        }
+        (median, self.compute_blast_point_cost(median, None, &identity).unwrap())
+    }
+
+    /// Find the minimal cost 'blast point' for the `triangular_number` cost.  The continuous
+    /// minimum is within half a step of the (weighted) mean, so we only need to check a few
+    /// points around it.
+    pub fn find_blast_point_triangular(&self) -> (u64, u64) {
+        let minima = *self.positions.iter().min().unwrap();
+        let maxima = *self.positions.iter().max().unwrap();
+        let total_weight : u64 = self.weights.iter().sum();
+        // Nobody to move, so the lowest point is as good as any
+        if total_weight == 0 { return (minima, 0); }
+        let weighted_sum : u64 = self.positions.iter().zip(&self.weights).map(|(p, w)| p * w).sum();
+        let mean = weighted_sum / total_weight;
+        let mut best : Option<(u64, u64)> = None;
+        for blast_point in max(minima, mean.saturating_sub(1))..=min(maxima, mean + 2) {
+            let cost = self.compute_blast_point_cost(blast_point, None, &triangular_number).unwrap();
+            if best.is_none() || cost < best.unwrap().1 { best = Some((blast_point, cost)); }
+        }
+        best.unwrap()
+    }
+
+    /// Find the minimal cost 'blast point' for any convex cost function (one whose step-to-step
+    /// increase never shrinks), in O(n log range).  The total cost is then convex too, so we
+    /// binary search for the lowest point where stepping right stops making things cheaper.
+    pub fn find_blast_point_convex<C: FuelCost + ?Sized>(&self, cost_fn : &C) -> (u64, u64) {
+        let mut low = *self.positions.iter().min().unwrap();
+        let mut high = *self.positions.iter().max().unwrap();
+        let cost = |blast_point| self.compute_blast_point_cost(blast_point, None, cost_fn).unwrap();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if cost(mid + 1) >= cost(mid) { high = mid; } else { low = mid + 1; }
+        }
+        (low, cost(low))
+    }
+
+    /// Find the best `k` blast points, where every crab goes to whichever is cheapest for it.
+    /// On a line with a convex cost, some optimal answer always sends a contiguous run of the
+    /// sorted crabs to each point, so we can solve exactly with dynamic programming over where
+    /// each run ends - O(k n^2 log n) for n distinct positions.  If there are fewer distinct
+    /// positions than `k`, we only need (and return) one point per position.  Crab kinds are
+    /// ignored, as the cost is fixed.
+    pub fn find_rendezvous_points(&self, k : usize, cost : RendezvousCost) -> Rendezvous {
+        assert!(k > 0, "Need at least one rendezvous point");
+        let groups = SortedGroups::new(&self.positions, &self.weights);
+        let n = groups.positions.len();
+        let k = min(k, n);
+
+        // best[g][j] is the cheapest way to send the first j distinct positions to g points,
+        // and split[g][j] is where the last of those runs starts.
+        let mut best = vec![vec![u64::MAX; n + 1]; k + 1];
+        let mut split = vec![vec![0; n + 1]; k + 1];
+        best[0][0] = 0;
+        for g in 1..=k {
+            for j in g..=n {
+                for i in (g - 1)..j {
+                    if best[g - 1][i] == u64::MAX { continue; }
+                    let candidate = best[g - 1][i] + groups.best_point(i, j, cost).1;
+                    if candidate < best[g][j] {
+                        best[g][j] = candidate;
+                        split[g][j] = i;
+                    }
+                }
+            }
+        }
+
+        let mut points = Vec::with_capacity(k);
+        let mut end = n;
+        for g in (1..=k).rev() {
+            let start = split[g][end];
+            points.push(groups.best_point(start, end, cost).0);
+            end = start;
+        }
+        points.reverse();
+
+        let assignment : Vec<usize> = self.positions.iter().map(|&pos| {
+            (0..points.len()).min_by_key(|&i| cost.cost(abs_diff(points[i], pos))).unwrap()
+        }).collect();
+        let total_cost = self.positions.iter().zip(&self.weights).zip(&assignment)
+            .map(|((&pos, weight), &i)| weight * cost.cost(abs_diff(points[i], pos)))
+            .sum();
+        assert_eq!(total_cost, best[k][n]);
+        Rendezvous { points, assignment, cost: total_cost }
+    }
+}
+
+/// The convex costs `find_rendezvous_points` knows how to solve exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RendezvousCost {
+    /// The `identity` cost from part one
+    Linear,
+    /// The `triangular_number` cost from part two
+    Triangular,
+}
+
+impl FuelCost for RendezvousCost {
+    fn cost(&self, dx: u64) -> u64 {
+        match self {
+            RendezvousCost::Linear => identity(dx),
+            RendezvousCost::Triangular => triangular_number(dx),
+        }
+    }
+}
+
+/// Where the blast points went, which one (by index into `points`) each crab went to, and the
+/// total fuel it cost.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rendezvous {
+    pub points : Vec<u64>,
+    pub assignment : Vec<usize>,
+    pub cost : u64,
+}
+
+/// The distinct crab positions in order, with prefix sums of weight, weight * position and
+/// weight * position^2, so we can find the cost of sending any run of them to a single point
+/// without looking at every crab in the run.
+struct SortedGroups {
+    positions : Vec<u64>,
+    weight : Vec<i128>,
+    first_moment : Vec<i128>,
+    second_moment : Vec<i128>,
+}
+
+impl SortedGroups {
+    fn new(positions : &[u64], weights : &[u64]) -> Self {
+        let mut sorted : Vec<(u64, u64)> = positions.iter().copied().zip(weights.iter().copied()).collect();
+        sorted.sort_unstable();
+        let mut groups = SortedGroups {
+            positions: Vec::new(), weight: vec![0], first_moment: vec![0], second_moment: vec![0]
+        };
+        for (pos, w) in sorted {
+            if groups.positions.last() != Some(&pos) {
+                groups.positions.push(pos);
+                groups.weight.push(*groups.weight.last().unwrap());
+                groups.first_moment.push(*groups.first_moment.last().unwrap());
+                groups.second_moment.push(*groups.second_moment.last().unwrap());
+            }
+            let (p, w) = (pos as i128, w as i128);
+            *groups.weight.last_mut().unwrap() += w;
+            *groups.first_moment.last_mut().unwrap() += w * p;
+            *groups.second_moment.last_mut().unwrap() += w * p * p;
+        }
+        groups
+    }
+
+    /// Cost of sending the positions in [start, end) to blast_point.
+    fn cost(&self, start : usize, end : usize, blast_point : u64, cost : RendezvousCost) -> u64 {
+        let b = blast_point as i128;
+        let mid = start + self.positions[start..end].partition_point(|&p| p < blast_point);
+        let (w_left, w_right) = (self.weight[mid] - self.weight[start], self.weight[end] - self.weight[mid]);
+        let (x_left, x_right) = (self.first_moment[mid] - self.first_moment[start], self.first_moment[end] - self.first_moment[mid]);
+        let distance = b * w_left - x_left + x_right - b * w_right;
+        let total = match cost {
+            RendezvousCost::Linear => distance,
+            RendezvousCost::Triangular => {
+                let w = w_left + w_right;
+                let squared = b * b * w - 2 * b * (x_left + x_right) + self.second_moment[end] - self.second_moment[start];
+                (squared + distance) / 2
+            },
+        };
+        total as u64
+    }
+
+    /// The lowest cheapest blast point for the positions in [start, end), and its cost.  Same
+    /// reasoning as `find_blast_point_linear` and `find_blast_point_triangular`.
+    fn best_point(&self, start : usize, end : usize, cost : RendezvousCost) -> (u64, u64) {
+        let total_weight = self.weight[end] - self.weight[start];
+        // Crabs we don't care about are free to move anywhere, and have no mean to aim for
+        if total_weight == 0 { return (self.positions[start], 0); }
+        let candidates : Vec<u64> = match cost {
+            RendezvousCost::Linear => {
+                let median = start + self.weight[start + 1..=end]
+                    .partition_point(|&w| 2 * (w - self.weight[start]) < total_weight);
+                vec![self.positions[median]]
+            },
+            RendezvousCost::Triangular => {
+                let mean = ((self.first_moment[end] - self.first_moment[start]) / total_weight) as u64;
+                (max(self.positions[start], mean.saturating_sub(1))..=min(self.positions[end - 1], mean + 2)).collect()
+            },
+        };
+        candidates.into_iter()
+            .map(|point| (point, self.cost(start, end, point, cost)))
+            .min_by_key(|&(point, cost)| (cost, point))
+            .unwrap()
+    }
+}
+
+/// Crabs that can move in D dimensions rather than just along a line.  Each crab has a weight
+/// and kind, just like in the 1-D CrabArmada.
+pub struct CrabSwarm<const D: usize> {
+    positions : Vec<[u64; D]>,
+    weights : Vec<u64>,
+    kinds : Vec<usize>,
+    /// The 1-D armada along each axis, kept up to date so the per-axis searches don't copy.
+    axes : Vec<CrabArmada>,
+}
+
+impl<const D: usize> CrabSwarm<D> {
+    /// Create a CrabSwarm from a vector, with every crab of weight 1 and kind 0.
+    pub fn from_vec(positions : Vec<[u64; D]>) -> Self {
+        let num_crabs = positions.len();
+        let mut swarm = CrabSwarm { positions, weights: vec![1; num_crabs], kinds: vec![0; num_crabs], axes: Vec::new() };
+        swarm.build_axes();
+        swarm
+    }
+
+    /// Give each crab (in the same order as the positions) a weight.
+    pub fn with_weights(mut self, weights : Vec<u64>) -> Self {
+        assert_eq!(weights.len(), self.positions.len());
+        self.weights = weights;
+        self.build_axes();
+        self
+    }
+
+    /// Give each crab (in the same order as the positions) a kind, for `FuelCost::cost_for_kind`.
+    pub fn with_kinds(mut self, kinds : Vec<usize>) -> Self {
+        assert_eq!(kinds.len(), self.positions.len());
+        self.kinds = kinds;
+        self.build_axes();
+        self
+    }
+
+    fn build_axes(&mut self) {
+        self.axes = (0..D).map(|axis| CrabArmada {
+            positions: self.positions.iter().map(|p| p[axis]).collect(),
+            weights: self.weights.clone(),
+            kinds: self.kinds.clone(),
+        }).collect();
+    }
+
+    /// The 1-D armada we get by only looking at one axis.
+    pub fn axis(&self, axis : usize) -> &CrabArmada {
+        &self.axes[axis]
+    }
+
+    /// Compute the cost of a blast point when each crab pays `cost_fn` separately along every
+    /// axis - e.g. `identity` gives Manhattan distance.
+    pub fn compute_blast_point_cost<C: FuelCost + ?Sized>(&self, blast_point : [u64; D], cost_fn : &C) -> u64 {
+        (0..D).map(|axis| self.axis(axis).compute_blast_point_cost(blast_point[axis], None, cost_fn).unwrap()).sum()
+    }
+
+    /// Find the minimal cost blast point when the cost is paid per axis (see
+    /// `compute_blast_point_cost`).  That makes the axes independent, so we solve each one with
+    /// the 1-D convex search.
+    pub fn find_blast_point_separable<C: FuelCost + ?Sized>(&self, cost_fn : &C) -> ([u64; D], u64) {
+        let mut blast_point = [0; D];
+        let mut total_cost = 0;
+        for (axis, coord) in blast_point.iter_mut().enumerate() {
+            let (best_point, best_cost) = self.axis(axis).find_blast_point_convex(cost_fn);
+            *coord = best_point;
+            total_cost += best_cost;
+        }
+        (blast_point, total_cost)
+    }
+
+    /// Find the minimal cost blast point under Manhattan distance - the median on every axis.
+    pub fn find_blast_point_manhattan(&self) -> ([u64; D], u64) {
+        let mut blast_point = [0; D];
+        let mut total_cost = 0;
+        for (axis, coord) in blast_point.iter_mut().enumerate() {
+            let (best_point, best_cost) = self.axis(axis).find_blast_point_linear();
+            *coord = best_point;
+            total_cost += best_cost;
+        }
+        (blast_point, total_cost)
+    }
+
+    /// Total weighted straight-line distance from every crab to a (not necessarily whole) point.
+    pub fn euclidean_cost(&self, blast_point : &[f64; D]) -> f64 {
+        self.positions.iter().zip(&self.weights)
+            .map(|(p, &w)| w as f64 * euclidean_distance(p, blast_point))
+            .sum()
+    }
+
+    /// Find the point minimising the total (weighted) straight-line distance - the geometric
+    /// median.  There's no closed form, so we use Weiszfeld's iteration from the centroid, which
+    /// moves to the average of the crabs weighted by the inverse of their distance, until it
+    /// moves less than `tolerance`.
+    pub fn find_blast_point_euclidean(&self, tolerance : f64) -> ([f64; D], f64) {
+        let total_weight : f64 = self.weights.iter().map(|&w| w as f64).sum();
+        let mut point = [0.0; D];
+        for (p, &w) in self.positions.iter().zip(&self.weights) {
+            for axis in 0..D { point[axis] += p[axis] as f64 * w as f64 / total_weight; }
+        }
+
+        for _ in 0..100_000 {
+            let mut numerator = [0.0; D];
+            let mut denominator = 0.0;
+            let mut weight_here = 0.0;
+            for (p, &w) in self.positions.iter().zip(&self.weights) {
+                let distance = euclidean_distance(p, &point);
+                if distance == 0.0 { weight_here += w as f64; continue; }
+                for axis in 0..D { numerator[axis] += w as f64 * p[axis] as f64 / distance; }
+                denominator += w as f64 / distance;
+            }
+            if denominator == 0.0 { break; } // Every crab is right here
+
+            // If we've landed on some crabs, we're done when they outweigh the pull of the rest
+            if weight_here > 0.0 && self.pull_towards_others(&point) <= weight_here { break; }
+
+            let mut next = [0.0; D];
+            for axis in 0..D { next[axis] = numerator[axis] / denominator; }
+            let moved = (0..D).map(|axis| (next[axis] - point[axis]).powi(2)).sum::<f64>().sqrt();
+            point = next;
+            if moved < tolerance { break; }
+        }
+
+        // Weiszfeld only creeps up on an answer that's exactly on a crab, so check the nearest
+        let nearest = self.positions.iter().zip(&self.weights)
+            .min_by(|(p, _), (q, _)| euclidean_distance(p, &point).total_cmp(&euclidean_distance(q, &point)));
+        if let Some((crab, _)) = nearest {
+            let crab_point = crab.map(|x| x as f64);
+            let weight_here : u64 = self.positions.iter().zip(&self.weights)
+                .filter(|(p, _)| *p == crab).map(|(_, w)| w).sum();
+            if self.pull_towards_others(&crab_point) <= weight_here as f64 { point = crab_point; }
+        }
+        (point, self.euclidean_cost(&point))
+    }
+
+    /// How strongly the crabs that aren't at `point` pull it away, in total.
+    fn pull_towards_others(&self, point : &[f64; D]) -> f64 {
+        let mut pull = [0.0; D];
+        for (p, &w) in self.positions.iter().zip(&self.weights) {
+            let distance = euclidean_distance(p, point);
+            if distance == 0.0 { continue; }
+            for axis in 0..D { pull[axis] += w as f64 * (p[axis] as f64 - point[axis]) / distance; }
+        }
+        pull.iter().map(|x| x * x).sum::<f64>().sqrt()
+    }
+}
+
+fn euclidean_distance<const D: usize>(p : &[u64; D], q : &[f64; D]) -> f64 {
+    (0..D).map(|axis| (p[axis] as f64 - q[axis]).powi(2)).sum::<f64>().sqrt()
+}
+
+/// The total cost at every blast point in a range, for one cost function.
+#[derive(Clone, Debug)]
+pub struct CostCurve {
+    pub name : String,
+    pub costs : Vec<(u64, u64)>,
+}
+
+impl CostCurve {
+    /// The lowest of the cheapest blast points, matching `find_blast_point`.
+    pub fn optimum(&self) -> (u64, u64) {
+        *self.costs.iter().min_by_key(|&&(point, cost)| (cost, point)).unwrap()
+    }
+
+    /// Plot the curve as text, `width` characters wide and `height` lines tall.  Each column
+    /// shows the cheapest point it covers, and the optimum is marked with an 'O'.
+    pub fn write_ascii<W: Write>(&self, writer : &mut W, width : usize, height : usize) -> io::Result<()> {
+        let width = min(width, self.costs.len());
+        let (optimum_point, optimum_cost) = self.optimum();
+        let max_cost = self.costs.iter().map(|&(_, cost)| cost).max().unwrap();
+        let columns : Vec<(u64, bool)> = (0..width).map(|col| {
+            let chunk = &self.costs[col * self.costs.len() / width..(col + 1) * self.costs.len() / width];
+            let cost = chunk.iter().map(|&(_, cost)| cost).min().unwrap();
+            (cost, chunk.iter().any(|&(point, _)| point == optimum_point))
+        }).collect();
+        let row_of = |cost : u64| {
+            let range = max(1, max_cost - optimum_cost) as f64;
+            ((max_cost - cost) as f64 / range * (height - 1) as f64).round() as usize
+        };
+
+        writeln!(writer, "{}: cheapest at {} for {}", self.name, optimum_point, optimum_cost)?;
+        for row in 0..height {
+            let label = if row == 0 { max_cost } else if row == height - 1 { optimum_cost } else { 0 };
+            let label = if row == 0 || row == height - 1 { label.to_string() } else { String::new() };
+            let line : String = columns.iter().map(|&(cost, is_optimum)| {
+                if row_of(cost) != row { ' ' } else if is_optimum { 'O' } else { '*' }
+            }).collect();
+            writeln!(writer, "{:>12} |{}", label, line.trim_end())?;
+        }
+        writeln!(writer, "{:>12} +{}", "", "-".repeat(width))?;
+        let (first, last) = (self.costs[0].0.to_string(), self.costs.last().unwrap().0.to_string());
+        writeln!(writer, "{:>12}  {}{:>pad$}", "", first, last, pad = width.saturating_sub(first.len()))
+    }
+}
+
+/// Write several cost curves over the same blast points as CSV - one column of costs per
+/// curve, then a column per curve with a 1 on the row of its optimum.
+pub fn write_cost_curves_csv<W: Write>(writer : &mut W, curves : &[CostCurve]) -> io::Result<()> {
+    let mut header = vec!["blast_point".to_string()];
+    header.extend(curves.iter().map(|c| c.name.clone()));
+    header.extend(curves.iter().map(|c| format!("{}_optimum", c.name)));
+    writeln!(writer, "{}", header.join(","))?;
+    let Some(first) = curves.first() else { return Ok(()) };
+    let optima : Vec<u64> = curves.iter().map(|c| c.optimum().0).collect();
+    for (i, &(point, _)) in first.costs.iter().enumerate() {
+        let mut row = vec![point.to_string()];
+        row.extend(curves.iter().map(|c| c.costs[i].1.to_string()));
+        row.extend(optima.iter().map(|&o| if o == point { "1" } else { "0" }.to_string()));
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+/// Escape the characters that would otherwise be read as XML markup.
+fn xml_escape(text : &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        .replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+/// Draw cost curves as an SVG line chart, each scaled to fill the height (the costs of
+/// different functions are wildly different) with a circle on its optimum.
+pub fn write_cost_curves_svg<W: Write>(writer : &mut W, curves : &[CostCurve], width : u32, height : u32) -> io::Result<()> {
+    const COLOURS : [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#9467bd", "#ff7f0e", "#8c564b"];
+    const MARGIN : f64 = 20.0;
+    writeln!(writer, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">", width, height)?;
+    for (i, curve) in curves.iter().enumerate() {
+        let colour = COLOURS[i % COLOURS.len()];
+        let (first, last) = (curve.costs[0].0 as f64, curve.costs.last().unwrap().0 as f64);
+        let (optimum_point, optimum_cost) = curve.optimum();
+        let max_cost = curve.costs.iter().map(|&(_, cost)| cost).max().unwrap() as f64;
+        let x = |point : u64| MARGIN + (point as f64 - first) / (last - first).max(1.0) * (width as f64 - 2.0 * MARGIN);
+        let y = |cost : u64| height as f64 - MARGIN
+            - (cost as f64 - optimum_cost as f64) / (max_cost - optimum_cost as f64).max(1.0) * (height as f64 - 2.0 * MARGIN);
+        let name = xml_escape(&curve.name);
+        let points : Vec<String> = curve.costs.iter().map(|&(p, c)| format!("{:.1},{:.1}", x(p), y(c))).collect();
+        writeln!(writer, "  <polyline fill=\"none\" stroke=\"{}\" points=\"{}\"><title>{}</title></polyline>",
+            colour, points.join(" "), name)?;
+        writeln!(writer, "  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"{}\"><title>{} optimum: {} costs {}</title></circle>",
+            x(optimum_point), y(optimum_cost), colour, name, optimum_point, optimum_cost)?;
+        writeln!(writer, "  <text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>", MARGIN, MARGIN + 15.0 * i as f64, colour, name)?;
+    }
+    writeln!(writer, "</svg>")
 }
 
 fn main() -> io::Result<()> {
+    // Usage: day7 [--csv FILE] [--svg FILE] [--plot]
+    let args : Vec<String> = std::env::args().collect();
     let armada = CrabArmada::from_file("input.txt");
-    let (best_point, best_cost) = armada.find_blast_point(identity);
+    let (best_point, best_cost) = armada.find_blast_point_linear();
     println!("Pt1: Blast Point {} with cost {}", best_point, best_cost);
 
-    let (best_point, best_cost) = armada.find_blast_point(triangular_number);
+    let (best_point, best_cost) = armada.find_blast_point_triangular();
     println!("Pt2: Blast Point {} with cost {}", best_point, best_cost);
 
+    for k in 2..=4 {
+        let rendezvous = armada.find_rendezvous_points(k, RendezvousCost::Triangular);
+        println!("Pt2 with {} blast points at {:?} costs {}", k, rendezvous.points, rendezvous.cost);
+    }
+
+    // The curves cost every point in the range, so only build them if we're going to show them
+    let flag_value = |flag : &str| args.iter().position(|x| x == flag).map(|i| args.get(i + 1).expect("Missing filename"));
+    let (csv, svg, plot) = (flag_value("--csv"), flag_value("--svg"), args.iter().any(|x| x == "--plot"));
+    if csv.is_some() || svg.is_some() || plot {
+        let curves = [armada.cost_curve("identity", &identity), armada.cost_curve("triangular_number", &triangular_number)];
+        if let Some(filename) = csv { write_cost_curves_csv(&mut File::create(filename)?, &curves)?; }
+        if let Some(filename) = svg { write_cost_curves_svg(&mut File::create(filename)?, &curves, 800, 400)?; }
+        if plot {
+            for curve in &curves { curve.write_ascii(&mut io::stdout(), 78, 15)?; }
+        }
+    }
+
     Ok(())
 }
 
@@ -83,18 +650,261 @@ fn main() -> io::Result<()> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
     #[test]
     fn test_example() {
         let armada = CrabArmada::from_vec(vec![16,1,2,0,4,2,7,1,2,14]);
         // Part 1
-        assert_eq!(armada.find_blast_point(identity), (2, 37));
-        assert_eq!(armada.compute_blast_point_cost(2, None, identity), Some(37));
-        assert_eq!(armada.compute_blast_point_cost(1, None, identity), Some(41));
-        assert_eq!(armada.compute_blast_point_cost(3, None, identity), Some(39));
-        assert_eq!(armada.compute_blast_point_cost(10, None, identity), Some(71));
+        assert_eq!(armada.find_blast_point(&identity), (2, 37));
+        assert_eq!(armada.compute_blast_point_cost(2, None, &identity), Some(37));
+        assert_eq!(armada.compute_blast_point_cost(1, None, &identity), Some(41));
+        assert_eq!(armada.compute_blast_point_cost(3, None, &identity), Some(39));
+        assert_eq!(armada.compute_blast_point_cost(10, None, &identity), Some(71));
 
         // Part 2
-        assert_eq!(armada.find_blast_point(triangular_number), (5, 168));
-        assert_eq!(armada.compute_blast_point_cost(2, None, triangular_number), Some(206));
+        assert_eq!(armada.find_blast_point(&triangular_number), (5, 168));
+        assert_eq!(armada.compute_blast_point_cost(2, None, &triangular_number), Some(206));
+    }
+
+    #[test]
+    fn test_fast_paths() {
+        let armada = CrabArmada::from_vec(vec![16,1,2,0,4,2,7,1,2,14]);
+        assert_eq!(armada.find_blast_point_linear(), (2, 37));
+        assert_eq!(armada.find_blast_point_triangular(), (5, 168));
+        assert_eq!(armada.find_blast_point_convex(&identity), (2, 37));
+        assert_eq!(armada.find_blast_point_convex(&triangular_number), (5, 168));
+        // With an even number of crabs everything between the middle two ties, so take the lowest
+        assert_eq!(CrabArmada::from_vec(vec![1, 5]).find_blast_point_linear(), (1, 4));
+        assert_eq!(CrabArmada::from_vec(vec![3]).find_blast_point_triangular(), (3, 0));
+    }
+
+    #[test]
+    fn test_fast_paths_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(2021);
+        let cube = |dx: u64| dx * dx * dx;
+        for _ in 0..500 {
+            let num_crabs = rng.gen_range(1..40);
+            let spread = rng.gen_range(1..200);
+            let armada = CrabArmada::from_vec((0..num_crabs).map(|_| rng.gen_range(0..spread)).collect());
+            assert_eq!(armada.find_blast_point_linear(), armada.find_blast_point(&identity));
+            assert_eq!(armada.find_blast_point_triangular(), armada.find_blast_point(&triangular_number));
+            for cost_fn in [identity, triangular_number, cube] {
+                assert_eq!(armada.find_blast_point_convex(&cost_fn), armada.find_blast_point(&cost_fn));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cost_families() {
+        assert_eq!(Polynomial { coefficients: vec![1, 0, 2] }.cost(3), 19);
+        assert_eq!(Capped { inner: triangular_number, cap: 10 }.cost(3), 6);
+        assert_eq!(Capped { inner: triangular_number, cap: 10 }.cost(5), 10);
+        let tiring = Piecewise { rates: vec![(0, 1), (10, 3)] };
+        assert_eq!(tiring.cost(4), 4);
+        assert_eq!(tiring.cost(10), 10);
+        assert_eq!(tiring.cost(12), 16);
+
+        // The triangular numbers are a polynomial, and a closure can carry its own parameters
+        let armada = CrabArmada::from_vec(vec![16,1,2,0,4,2,7,1,2,14]);
+        let doubled_triangular = Polynomial { coefficients: vec![0, 1, 1] };
+        assert_eq!(armada.find_blast_point(&doubled_triangular), (5, 2 * 168));
+        let rate = 3;
+        assert_eq!(armada.find_blast_point(&|dx| rate * dx), (2, 3 * 37));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_weights_and_kinds() {
+        // A weight of two is the same as two crabs at the same place
+        let weighted = CrabArmada::from_vec(vec![16,1,2,0,4,7,14]).with_weights(vec![1,2,3,1,1,1,1]);
+        assert_eq!(weighted.find_blast_point(&identity), (2, 37));
+        assert_eq!(weighted.find_blast_point_linear(), (2, 37));
+        assert_eq!(weighted.find_blast_point_triangular(), (5, 168));
+
+        // With no weight at all, nothing costs anything
+        let weightless = CrabArmada::from_vec(vec![5, 9]).with_weights(vec![0, 0]);
+        assert_eq!(weightless.find_blast_point(&triangular_number), (5, 0));
+        assert_eq!(weightless.find_blast_point_linear(), (5, 0));
+        assert_eq!(weightless.find_blast_point_triangular(), (5, 0));
+        assert_eq!(weightless.find_blast_point_convex(&triangular_number), (5, 0));
+
+        // Big slow crabs at 0 pull the blast point towards them
+        let fleet = CrabArmada::from_vec(vec![0, 0, 10, 10, 10]).with_kinds(vec![1, 1, 0, 0, 0]);
+        let costs = PerKind::new(vec![Box::new(identity), Box::new(|dx| 5 * dx)]);
+        assert_eq!(fleet.find_blast_point(&costs), (0, 30));
+        assert_eq!(fleet.find_blast_point_convex(&costs), (0, 30));
+        let capped = Capped { inner: PerKind::new(vec![Box::new(identity), Box::new(|dx| 5 * dx)]), cap: 20 };
+        assert_eq!(fleet.compute_blast_point_cost(0, None, &capped), Some(30));
+        assert_eq!(fleet.compute_blast_point_cost(10, None, &capped), Some(40));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one kind")]
+    fn test_per_kind_needs_a_cost() {
+        PerKind::new(Vec::new());
+    }
+
+    #[test]
+    fn test_weighted_fast_paths_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let tiring = Piecewise { rates: vec![(0, 1), (5, 2), (20, 7)] };
+        for _ in 0..300 {
+            let num_crabs = rng.gen_range(1..30);
+            let armada = CrabArmada::from_vec((0..num_crabs).map(|_| rng.gen_range(0..100)).collect())
+                .with_weights((0..num_crabs).map(|_| rng.gen_range(1..10)).collect());
+            assert_eq!(armada.find_blast_point_linear(), armada.find_blast_point(&identity));
+            assert_eq!(armada.find_blast_point_triangular(), armada.find_blast_point(&triangular_number));
+            assert_eq!(armada.find_blast_point_convex(&tiring), armada.find_blast_point(&tiring));
+        }
+    }
+
+    #[test]
+    fn test_swarm_separable() {
+        let mut rng = StdRng::seed_from_u64(34);
+        for _ in 0..50 {
+            let num_crabs = rng.gen_range(1..15);
+            let swarm = CrabSwarm::<2>::from_vec((0..num_crabs).map(|_| [rng.gen_range(0..20), rng.gen_range(0..20)]).collect())
+                .with_weights((0..num_crabs).map(|_| rng.gen_range(1..4)).collect());
+            // Brute force over the whole grid, keeping the lowest point in (x, y) order
+            let mut best : Option<([u64; 2], u64)> = None;
+            for x in 0..20 {
+                for y in 0..20 {
+                    let cost = swarm.compute_blast_point_cost([x, y], &triangular_number);
+                    if best.is_none() || cost < best.unwrap().1 { best = Some(([x, y], cost)); }
+                }
+            }
+            assert_eq!(swarm.find_blast_point_separable(&triangular_number), best.unwrap());
+            assert_eq!(swarm.find_blast_point_manhattan(), swarm.find_blast_point_separable(&identity));
+        }
+
+        let swarm = CrabSwarm::from_vec(vec![[0, 0, 0], [2, 4, 6], [4, 1, 3]]);
+        assert_eq!(swarm.find_blast_point_manhattan(), ([2, 1, 3], 4 + 4 + 6));
+    }
+
+    #[test]
+    fn test_swarm_euclidean() {
+        // The middle of a square
+        let square = CrabSwarm::from_vec(vec![[0, 0], [0, 10], [10, 0], [10, 10]]);
+        let (point, cost) = square.find_blast_point_euclidean(1e-9);
+        assert!((point[0] - 5.0).abs() < 1e-6 && (point[1] - 5.0).abs() < 1e-6);
+        assert!((cost - 4.0 * 50f64.sqrt()).abs() < 1e-6);
+
+        // In a line it's the median, which we'll land right on top of
+        let line = CrabSwarm::from_vec(vec![[0, 0], [1, 0], [10, 0]]);
+        let (point, cost) = line.find_blast_point_euclidean(1e-9);
+        assert!((point[0] - 1.0).abs() < 1e-6 && point[1].abs() < 1e-6);
+        assert!((cost - 10.0).abs() < 1e-6);
+
+        // A heavy enough crab doesn't move at all
+        let heavy = CrabSwarm::from_vec(vec![[3, 3], [0, 0], [9, 1]]).with_weights(vec![5, 1, 1]);
+        assert_eq!(heavy.find_blast_point_euclidean(1e-9).0, [3.0, 3.0]);
+
+        // And for random crabs, nudging the answer in any direction doesn't help
+        let mut rng = StdRng::seed_from_u64(35);
+        for _ in 0..20 {
+            let swarm = CrabSwarm::<3>::from_vec((0..7).map(|_| [rng.gen_range(0..50), rng.gen_range(0..50), rng.gen_range(0..50)]).collect());
+            let (point, cost) = swarm.find_blast_point_euclidean(1e-9);
+            for axis in 0..3 {
+                for delta in [-0.01, 0.01] {
+                    let mut nudged = point;
+                    nudged[axis] += delta;
+                    assert!(swarm.euclidean_cost(&nudged) >= cost - 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rendezvous() {
+        let armada = CrabArmada::from_vec(vec![16,1,2,0,4,2,7,1,2,14]);
+        // One point is just the normal answer
+        let one = armada.find_rendezvous_points(1, RendezvousCost::Linear);
+        assert_eq!((one.points, one.cost), (vec![2], 37));
+        let one = armada.find_rendezvous_points(1, RendezvousCost::Triangular);
+        assert_eq!((one.points, one.cost), (vec![5], 168));
+
+        // Two points split off the far crabs
+        let two = armada.find_rendezvous_points(2, RendezvousCost::Linear);
+        assert_eq!(two, Rendezvous { points: vec![2, 14], assignment: vec![1,0,0,0,0,0,0,0,0,1], cost: 13 });
+
+        // And with a point for every distinct position, nobody moves
+        let all = armada.find_rendezvous_points(20, RendezvousCost::Triangular);
+        assert_eq!((all.points, all.cost), (vec![0, 1, 2, 4, 7, 14, 16], 0));
+
+        // Crabs with no weight cost nothing wherever they end up
+        let weightless = CrabArmada::from_vec(vec![0, 5, 9]).with_weights(vec![0, 1, 1]);
+        for cost in [RendezvousCost::Linear, RendezvousCost::Triangular] {
+            assert_eq!(weightless.find_rendezvous_points(2, cost).cost, 0);
+        }
+        let alone = CrabArmada::from_vec(vec![3, 8]).with_weights(vec![0, 0]);
+        assert_eq!(alone.find_rendezvous_points(1, RendezvousCost::Triangular).cost, 0);
+    }
+
+    #[test]
+    fn test_rendezvous_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(35);
+        for _ in 0..200 {
+            let num_crabs = rng.gen_range(1..10);
+            let armada = CrabArmada::from_vec((0..num_crabs).map(|_| rng.gen_range(0..12)).collect())
+                .with_weights((0..num_crabs).map(|_| rng.gen_range(1..5)).collect());
+            for cost in [RendezvousCost::Linear, RendezvousCost::Triangular] {
+                for k in 1..=3 {
+                    // Try every set of k points (allowing repeats, which covers fewer distinct positions)
+                    let mut best = u64::MAX;
+                    for combination in 0..12usize.pow(k as u32) {
+                        let points : Vec<u64> = (0..k).map(|i| (combination / 12usize.pow(i as u32) % 12) as u64).collect();
+                        let cost = armada.positions.iter().zip(&armada.weights)
+                            .map(|(&pos, w)| w * points.iter().map(|&pt| cost.cost(abs_diff(pt, pos))).min().unwrap())
+                            .sum();
+                        best = min(best, cost);
+                    }
+                    let rendezvous = armada.find_rendezvous_points(k, cost);
+                    assert_eq!(rendezvous.cost, best);
+                    assert!(rendezvous.points.len() <= k);
+                    assert_eq!(rendezvous.assignment.len(), num_crabs);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cost_curves() {
+        let armada = CrabArmada::from_vec(vec![16,1,2,0,4,2,7,1,2,14]);
+        let curves = [armada.cost_curve("identity", &identity), armada.cost_curve("triangular", &triangular_number)];
+        assert_eq!(curves[0].costs.len(), 17);
+        assert_eq!(curves[0].costs[10], (10, 71));
+        assert_eq!(curves[0].optimum(), armada.find_blast_point(&identity));
+        assert_eq!(curves[1].optimum(), armada.find_blast_point(&triangular_number));
+
+        let mut csv = Vec::new();
+        write_cost_curves_csv(&mut csv, &curves).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines : Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "blast_point,identity,triangular,identity_optimum,triangular_optimum");
+        assert_eq!(lines[3], "2,37,206,1,0");
+        assert_eq!(lines[6], "5,45,168,0,1");
+        assert_eq!(lines.len(), 18);
+
+        let mut svg = Vec::new();
+        write_cost_curves_svg(&mut svg, &curves, 400, 200).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        assert!(svg.contains("<title>triangular optimum: 5 costs 168</title>"));
+
+        // Names are escaped, and no curves at all is just a header
+        let mut svg = Vec::new();
+        write_cost_curves_svg(&mut svg, &[armada.cost_curve("a<b & c", &identity)], 400, 200).unwrap();
+        assert!(String::from_utf8(svg).unwrap().contains(">a&lt;b &amp; c</text>"));
+        let mut csv = Vec::new();
+        write_cost_curves_csv(&mut csv, &[]).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "blast_point\n");
+
+        let mut plot = Vec::new();
+        curves[1].write_ascii(&mut plot, 17, 5).unwrap();
+        let plot = String::from_utf8(plot).unwrap();
+        let lines : Vec<&str> = plot.lines().collect();
+        assert_eq!(lines[0], "triangular: cheapest at 5 for 168");
+        assert_eq!(lines.len(), 8);
+        // The optimum is in the bottom row, in the column for blast point 5
+        assert_eq!(lines[5].find('O'), Some(14 + 5));
+    }
+}
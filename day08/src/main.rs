@@ -1,22 +1,227 @@
+use std::fmt;
 use std::fs::File;
 use std::collections::HashMap;
 use std::io::{self, prelude::*, BufReader};
 use std::str::FromStr;
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
 
-fn segments_to_bits(segments: &str) -> u8 {
-    let mut bitmask = 0;
+fn segments_to_bits(segments: &str, num_segments: usize) -> Result<SegmentMask, DecodeError> {
+    let mut bitmask : SegmentMask = 0;
     for b in segments.bytes() {
-        let places = b-('a' as u8);
-        bitmask |= 1 << places;
+        if !(b'a'..b'a' + num_segments as u8).contains(&b) || bitmask & (1 << (b - b'a')) != 0 {
+            return Err(DecodeError::BadToken(segments.to_string()));
+        }
+        bitmask |= 1 << (b - b'a');
     }
-    return bitmask
+    if bitmask == 0 { return Err(DecodeError::BadToken(segments.to_string())); }
+    Ok(bitmask)
+}
+
+fn bits_to_segments(mask: SegmentMask) -> String {
+    (0..SegmentMask::BITS as u8).filter(|s| mask & (1 << s) != 0).map(|s| (b'a' + s) as char).collect()
 }
 
-type SegmentMask = u8;
+type SegmentMask = u32;
+/// Which glyph of a display is showing, as an index into its glyph table.  On a seven segment
+/// display that's just the digit.
 type Digit = u8;
+/// A wiring maps each (scrambled) wire to the segment it's really connected to - wiring[w] is
+/// the true segment for wire w.
+pub type Wiring = Vec<u8>;
+
+/// The segments lit for each digit on a correctly wired seven segment display, with a-g as
+/// bits 0-6:
+///      aaaa
+///     b    c
+///     b    c
+///      dddd
+///     e    f
+///     e    f
+///      gggg
+const DIGIT_SEGMENTS : [&str; 10] = ["abcefg", "cf", "acdeg", "acdfg", "bcdf", "abdfg", "abdefg", "acf", "abcdefg", "abcdfg"];
+
+/// Digits then letters on a fourteen segment display.  The outside is a-f as on a seven
+/// segment display but going round clockwise from the top, then g and h are the left and right
+/// halves of the middle bar, and i-n are the inner segments:
+///      aaaaaaa
+///     f i j k b
+///     f  ijk  b
+///      ggg hhh
+///     e  lmn  c
+///     e l m n c
+///      ddddddd
+const FOURTEEN_SEGMENT_GLYPHS : [(char, &str); 36] = [
+    ('0', "abcdefkl"), ('1', "bck"), ('2', "abdegh"), ('3', "abcdh"), ('4', "bcfgh"),
+    ('5', "adfgn"), ('6', "acdefgh"), ('7', "abc"), ('8', "abcdefgh"), ('9', "abcdfgh"),
+    ('A', "abcefgh"), ('B', "abcdhjm"), ('C', "adef"), ('D', "abcdjm"), ('E', "adefg"),
+    ('F', "aefg"), ('G', "acdefh"), ('H', "bcefgh"), ('I', "adjm"), ('J', "bcde"),
+    ('K', "efgkn"), ('L', "def"), ('M', "bcefik"), ('N', "bcefin"), ('O', "abcdef"),
+    ('P', "abefgh"), ('Q', "abcdefn"), ('R', "abefghn"), ('S', "acdfgh"), ('T', "ajm"),
+    ('U', "bcdef"), ('V', "efkl"), ('W', "bcefln"), ('X', "ikln"), ('Y', "ikm"), ('Z', "adkl"),
+];
+
+/// Where each fourteen segment display segment is on a sixteen segment display, which splits
+/// the top and bottom bars into two halves each.  Fourteen segment 'a' (the top) becomes 'a'
+/// (top left) and 'b' (top right), 'b' becomes 'c' and so on clockwise round the outside, so
+/// the bottom is 'e' (right) and 'f' (left), and then through the middle.
+const SIXTEEN_FROM_FOURTEEN : [&str; 14] = ["ab", "c", "d", "ef", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p"];
+
+/// Glyphs on a sixteen segment display that use half of a bar, and so look different from the
+/// fourteen segment ones.  Without these nothing would tell the two halves of a bar apart.
+const SIXTEEN_SEGMENT_OVERRIDES : [(char, &str); 2] = [('1', "aeflo"), ('J', "abfglo")];
+
+/// The kind of display we're looking at - how many segments it has, and which segments make up
+/// each glyph it can show.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplayType {
+    pub num_segments: usize,
+    pub glyphs: Vec<(char, SegmentMask)>,
+}
+
+impl DisplayType {
+    /// A display from its glyphs, each written as the letters of its segments.
+    pub fn from_glyphs(num_segments: usize, glyphs: &[(char, &str)]) -> Self {
+        assert!(num_segments <= SegmentMask::BITS as usize, "Too many segments for a SegmentMask");
+        DisplayType {
+            num_segments,
+            glyphs: glyphs.iter().map(|&(symbol, segments)| (symbol, segments_to_bits(segments, num_segments).unwrap())).collect()
+        }
+    }
+
+    /// The digits 0-9 on a seven segment display, as in the puzzle.
+    pub fn seven_segment() -> Self {
+        let glyphs : Vec<(char, &str)> = DIGIT_SEGMENTS.iter().enumerate()
+            .map(|(digit, &segments)| ((b'0' + digit as u8) as char, segments))
+            .collect();
+        DisplayType::from_glyphs(7, &glyphs)
+    }
+
+    /// Digits and capital letters on a fourteen segment display.
+    pub fn fourteen_segment() -> Self {
+        DisplayType::from_glyphs(14, &FOURTEEN_SEGMENT_GLYPHS)
+    }
+
+    /// Digits and capital letters on a sixteen segment display.
+    pub fn sixteen_segment() -> Self {
+        let glyphs : Vec<(char, String)> = FOURTEEN_SEGMENT_GLYPHS.iter().map(|&(symbol, segments)| {
+            let overridden = SIXTEEN_SEGMENT_OVERRIDES.iter().find(|&&(s, _)| s == symbol);
+            match overridden {
+                Some(&(_, segments)) => (symbol, segments.to_string()),
+                None => (symbol, segments.bytes().map(|b| SIXTEEN_FROM_FOURTEEN[(b - b'a') as usize]).collect()),
+            }
+        }).collect();
+        let glyphs : Vec<(char, &str)> = glyphs.iter().map(|(symbol, segments)| (*symbol, segments.as_str())).collect();
+        DisplayType::from_glyphs(16, &glyphs)
+    }
+
+    fn all_segments(&self) -> SegmentMask {
+        ((1u64 << self.num_segments) - 1) as SegmentMask
+    }
+
+    /// The glyph a (correctly wired) mask shows, if any.
+    pub fn glyph_of(&self, mask: SegmentMask) -> Option<Digit> {
+        self.glyphs.iter().position(|&(_, glyph)| glyph == mask).map(|g| g as Digit)
+    }
+
+    /// The symbol shown by glyph number `glyph`.
+    pub fn symbol(&self, glyph: Digit) -> char {
+        self.glyphs[glyph as usize].0
+    }
+
+    /// The glyph number that shows `symbol`, if the display can show it at all.
+    pub fn glyph_for(&self, symbol: char) -> Option<Digit> {
+        self.glyphs.iter().position(|&(s, _)| s == symbol).map(|g| g as Digit)
+    }
+}
+
+/// Apply a wiring to a scrambled mask, giving the segments that really light up.
+fn apply_wiring(wiring: &[u8], mask: SegmentMask) -> SegmentMask {
+    (0..wiring.len()).filter(|w| mask & (1 << w) != 0).fold(0, |acc, w| acc | (1 << wiring[w]))
+}
+
+/// Find every wiring under which each of `patterns` lights up a real glyph on `display`.  First
+/// we narrow down the segments each wire could be by looking at pattern sizes - a pattern of k
+/// wires can only be a glyph with k segments, so its wires must be among those glyphs'
+/// segments, and its missing wires can't be a segment all of those glyphs share.  Then whenever
+/// a wire is pinned down nobody else can have it.  Whatever's still open gets a backtracking
+/// search, which after each assignment checks every pattern could still be one of its glyphs.
+pub fn solve_wirings(display: &DisplayType, patterns: &[SegmentMask]) -> Vec<Wiring> {
+    let n = display.num_segments;
+    let all_segments = display.all_segments();
+    let mut domains = vec![all_segments; n];
+    let mut candidates = Vec::with_capacity(patterns.len());
+    for &pattern in patterns {
+        let same_size : Vec<SegmentMask> = display.glyphs.iter().map(|&(_, g)| g)
+            .filter(|g| g.count_ones() == pattern.count_ones()).collect();
+        let union = same_size.iter().fold(0, |acc, g| acc | g);
+        let intersection = same_size.iter().fold(all_segments, |acc, g| acc & g);
+        for (wire, domain) in domains.iter_mut().enumerate() {
+            if pattern & (1 << wire) != 0 { *domain &= union; } else { *domain &= !intersection; }
+        }
+        candidates.push(same_size);
+    }
+    loop {
+        let mut changed = false;
+        for wire in 0..n {
+            if domains[wire].count_ones() != 1 { continue; }
+            for other in (0..n).filter(|&other| other != wire) {
+                if domains[other] & domains[wire] != 0 {
+                    domains[other] &= !domains[wire];
+                    changed = true;
+                }
+            }
+        }
+        if !changed { break; }
+    }
+
+    // Assign the most constrained wires first
+    let mut order : Vec<usize> = (0..n).collect();
+    order.sort_by_key(|&wire| domains[wire].count_ones());
+    let mut solver = WiringSearch { patterns, candidates, domains, order, wiring: vec![0u8; n], solutions: Vec::new() };
+    solver.search(0, 0);
+    solver.solutions
+}
+
+struct WiringSearch<'a> {
+    patterns: &'a [SegmentMask],
+    candidates: Vec<Vec<SegmentMask>>,
+    domains: Vec<SegmentMask>,
+    order: Vec<usize>,
+    wiring: Wiring,
+    solutions: Vec<Wiring>,
+}
+
+impl<'a> WiringSearch<'a> {
+    fn search(&mut self, depth: usize, used: SegmentMask) {
+        if depth == self.order.len() {
+            self.solutions.push(self.wiring.clone());
+            return;
+        }
+        let wire = self.order[depth];
+        let assigned : SegmentMask = self.order[..=depth].iter().fold(0, |acc, w| acc | (1 << w));
+        for segment in 0..self.wiring.len() as u8 {
+            if self.domains[wire] & (1 << segment) == 0 || used & (1 << segment) != 0 { continue; }
+            self.wiring[wire] = segment;
+            if self.consistent(assigned) {
+                self.search(depth + 1, used | (1 << segment));
+            }
+        }
+    }
+
+    /// Can every pattern still be one of its candidate glyphs?  The wires we've assigned that
+    /// are lit must be segments of the glyph, and those that aren't lit must not be.
+    fn consistent(&self, assigned: SegmentMask) -> bool {
+        self.patterns.iter().zip(&self.candidates).all(|(&pattern, candidates)| {
+            let lit = apply_wiring(&self.wiring, pattern & assigned);
+            let unlit = apply_wiring(&self.wiring, !pattern & assigned);
+            candidates.iter().any(|&glyph| glyph & lit == lit && glyph & unlit == 0)
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct DisplayObservation {
+    display: DisplayType,
     input_masks: Vec<SegmentMask>,
     inputs : Vec<Digit>,
     output_masks: Vec<SegmentMask>,
@@ -25,101 +230,305 @@ pub struct DisplayObservation {
     segments: HashMap<Digit, SegmentMask>
 }
 
-#[derive(Debug)]
-pub struct ParseError;
+/// Everything that can go wrong reading or decoding an observation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A pattern that's empty, has letters other than the display's segments (a-g on a seven
+    /// segment display), or has the same letter twice.
+    BadToken(String),
+    /// There's no "|" separating the inputs from the outputs (or there's more than one).
+    MissingSeparator,
+    /// More input patterns than the display has glyphs.
+    TooManyInputs(usize),
+    /// The same input pattern appeared twice.
+    DuplicatePattern(String),
+    /// No wiring makes every pattern a glyph.
+    NoConsistentMapping,
+    /// With every glyph among the inputs, every output must be one of them.
+    OutputNotAmongInputs(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::BadToken(token) => write!(f, "bad pattern '{}'", token),
+            DecodeError::MissingSeparator => write!(f, "expected a single '|' between inputs and outputs"),
+            DecodeError::TooManyInputs(count) => write!(f, "{} inputs, more than the display has glyphs", count),
+            DecodeError::DuplicatePattern(pattern) => write!(f, "input pattern '{}' appears more than once", pattern),
+            DecodeError::NoConsistentMapping => write!(f, "no wiring makes every pattern a glyph"),
+            DecodeError::OutputNotAmongInputs(pattern) => write!(f, "output pattern '{}' isn't among the inputs", pattern),
+        }
+    }
+}
 
 impl FromStr for DisplayObservation {
-    type Err = ParseError;
+    type Err = DecodeError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DisplayObservation::parse_with(DisplayType::seven_segment(), s)
+    }
+}
+
+impl DisplayObservation {
+    /// Parse an observation of a given type of display.
+    pub fn parse_with(display: DisplayType, s: &str) -> Result<Self, DecodeError> {
         let parts : Vec<&str> = s.split_whitespace().collect();
-        let (input_parts, output_parts) = parts.split_at(11);
-        let input_masks: Vec<u8> = (*input_parts)[..10].iter().map(|segments| segments_to_bits(segments)).collect();
-        let output_masks: Vec<u8> = (*output_parts)[..4].iter().map(|segments| segments_to_bits(segments)).collect();
-        assert_eq!(input_masks.len(), 10);
-        assert_eq!(output_masks.len(), 4);
-        Ok(DisplayObservation{
-            input_masks:input_masks,
+        let separators : Vec<usize> = parts.iter().enumerate().filter(|(_, &p)| p == "|").map(|(i, _)| i).collect();
+        let (input_parts, output_parts) = match separators.as_slice() {
+            [separator] => (&parts[..*separator], &parts[separator + 1..]),
+            _ => return Err(DecodeError::MissingSeparator),
+        };
+        if input_parts.len() > display.glyphs.len() { return Err(DecodeError::TooManyInputs(input_parts.len())); }
+        let to_masks = |parts: &[&str]| parts.iter()
+            .map(|segments| segments_to_bits(segments, display.num_segments))
+            .collect::<Result<Vec<_>, _>>();
+        let input_masks = to_masks(input_parts)?;
+        let output_masks = to_masks(output_parts)?;
+        for (i, mask) in input_masks.iter().enumerate() {
+            if input_masks[..i].contains(mask) { return Err(DecodeError::DuplicatePattern(input_parts[i].to_string())); }
+        }
+        Ok(DisplayObservation::new(display, input_masks, output_masks))
+    }
+
+    /// Create a seven segment observation from scrambled masks directly.  There can be any
+    /// number of inputs (including none) - the fewer there are, the more likely the wiring is
+    /// ambiguous.
+    pub fn from_masks(input_masks: Vec<SegmentMask>, output_masks: Vec<SegmentMask>) -> Self {
+        DisplayObservation::new(DisplayType::seven_segment(), input_masks, output_masks)
+    }
+
+    /// Create an observation of any type of display from scrambled masks.
+    pub fn new(display: DisplayType, input_masks: Vec<SegmentMask>, output_masks: Vec<SegmentMask>) -> Self {
+        DisplayObservation {
+            display,
+            input_masks,
             inputs: Vec::new(),
-            output_masks:output_masks,
+            output_masks,
             outputs: Vec::new(),
             digits: HashMap::new(),
             segments: HashMap::new()
-        })
+        }
     }
-}
 
-impl DisplayObservation {
     fn set_mapping(&mut self, digit: Digit, mask: SegmentMask) {
         self.digits.insert(mask, digit);
         self.segments.insert(digit, mask);
     }
 
-    fn compute_mapping(&mut self) {
-        // Start by finding 1/4/7/8, which have unique segment counts
-        for mask in self.input_masks.clone() {
-            match mask.count_ones() {
-                2 => { self.set_mapping(1, mask); },
-                4 => { self.set_mapping(4, mask); },    
-                3 => { self.set_mapping(7, mask); },
-                7 => { self.set_mapping(8, mask); },
-                _ => { },
+    /// Every wiring consistent with everything we've seen lit up, inputs and outputs alike.
+    pub fn possible_wirings(&self) -> Vec<Wiring> {
+        let mut patterns = self.input_masks.clone();
+        patterns.extend(&self.output_masks);
+        patterns.sort_unstable();
+        patterns.dedup();
+        solve_wirings(&self.display, &patterns)
+    }
+
+    /// The glyph a scrambled mask shows under a given wiring, if any.
+    fn decode(&self, wiring: &[u8], mask: SegmentMask) -> Option<Digit> {
+        self.display.glyph_of(apply_wiring(wiring, mask))
+    }
+
+    /// Draw the outputs of a seven segment display twice, side by side - first as the garbled
+    /// digits we'd see if each wire letter lit the segment of the same name, then as they should
+    /// look once rewired.
+    /// There's nothing to draw for other displays, or before `compute_mapping` finds the outputs.
+    pub fn render_outputs(&self) -> Option<String> {
+        if self.display != DisplayType::seven_segment() || self.outputs.len() != self.output_masks.len() {
+            return None;
+        }
+        let corrected : Vec<SegmentMask> = self.outputs.iter().map(|&digit| self.display.glyphs[digit as usize].1).collect();
+        let scrambled = render_seven_segments(&self.output_masks);
+        let corrected = render_seven_segments(&corrected);
+        let arrows = ["        ", "  -->   ", "        "];
+        let lines : Vec<String> = (0..3).map(|row| format!("{}{}{}", scrambled[row], arrows[row], corrected[row])).collect();
+        Some(lines.join("\n"))
+    }
+
+    /// The outputs as the symbols they show, once `compute_mapping` has found them.
+    pub fn output_text(&self) -> String {
+        self.outputs.iter().map(|&glyph| self.display.symbol(glyph)).collect()
+    }
+
+    /// Every distinct reading of the outputs across all the possible wirings.  Even when the
+    /// wiring is ambiguous, the outputs often aren't.
+    pub fn possible_outputs(&self) -> Vec<Vec<Digit>> {
+        let mut readings : Vec<Vec<Digit>> = self.possible_wirings().iter()
+            .map(|wiring| self.output_masks.iter().map(|&mask| self.decode(wiring, mask).unwrap()).collect())
+            .collect();
+        readings.sort_unstable();
+        readings.dedup();
+        readings
+    }
+
+    /// Work out the wiring, and if there's only one, use it to set the inputs and outputs.
+    /// Returns all the wirings that fit, so the caller can see if (and how) it's ambiguous.
+    pub fn compute_mapping(&mut self) -> Result<Vec<Wiring>, DecodeError> {
+        if self.input_masks.len() == self.display.glyphs.len() {
+            if let Some(mask) = self.output_masks.iter().find(|mask| !self.input_masks.contains(mask)) {
+                return Err(DecodeError::OutputNotAmongInputs(bits_to_segments(*mask)));
             }
         }
-        // Top Left and Middle segments are only difference between 1 and 4
-        let tl_and_middle_segments : SegmentMask = self.segments.get(&4).unwrap() - self.segments.get(&1).unwrap();
-
-        // Now use 1,4,7,8 to derive the rest
-        for mask in self.input_masks.clone() {
-            match mask.count_ones() {
-                5 => {
-                    // 2,3,5 all have five segments
-                    if mask & self.segments[&1] == self.segments[&1] {
-                        // 3 contains all segments in 1
-                        self.set_mapping(3, mask);
-                    } else if mask & tl_and_middle_segments == tl_and_middle_segments {
-                        // 5 contains middle and top left segments
-                        self.set_mapping(5, mask);
-                    } else {
-                        self.set_mapping(2, mask);
-                    }
-                },
-                6 => {
-                    // 6,9,0 all have six segments
-                    if mask & self.segments[&4] == self.segments[&4] {
-                        // 4 is a strict subset of 9
-                        self.set_mapping(9, mask);
-                    } else if mask & self.segments[&1] == self.segments[&1] {
-                        // 1 is a strict subset of 0
-                        self.set_mapping(0, mask);
-                    } else {
-                        self.set_mapping(6, mask);
-                    }
-                },
-                _ => { }
+        let wirings = self.possible_wirings();
+        if wirings.is_empty() { return Err(DecodeError::NoConsistentMapping); }
+        if let [wiring] = wirings.as_slice() {
+            for (glyph, &(_, true_mask)) in self.display.glyphs.clone().iter().enumerate() {
+                let scrambled = scramble(wiring, true_mask);
+                self.set_mapping(glyph as Digit, scrambled);
             }
+            self.inputs = self.input_masks.iter().map(|mask| self.digits[mask]).collect();
+            self.outputs = self.output_masks.iter().map(|mask| self.digits[mask]).collect();
         }
-        // Now run through one last time, and set inputs and outputs based on mapping
-        for mask in &self.input_masks { self.inputs.push(self.digits[&mask]); }
-        for mask in &self.output_masks { self.outputs.push(self.digits[&mask]); }
+        Ok(wirings)
     }
 }
 
+/// The wires that light up to show `true_mask` through a wiring - the inverse of apply_wiring.
+fn scramble(wiring: &[u8], true_mask: SegmentMask) -> SegmentMask {
+    (0..wiring.len()).filter(|&w| true_mask & (1 << wiring[w]) != 0).fold(0, |acc, w| acc | (1 << w))
+}
+
+/// Draw seven segment masks as three lines of ASCII art, one digit after another:
+///      _     _
+///     |_|   |_
+///     |_|    _|
+fn render_seven_segments(masks: &[SegmentMask]) -> [String; 3] {
+    let mut rows = [String::new(), String::new(), String::new()];
+    for &mask in masks {
+        let lit = |segment: u8, c: char| if mask & (1 << segment) != 0 { c } else { ' ' };
+        rows[0].extend([' ', lit(0, '_'), ' ', ' ']);
+        rows[1].extend([lit(1, '|'), lit(3, '_'), lit(2, '|'), ' ']);
+        rows[2].extend([lit(4, '|'), lit(6, '_'), lit(5, '|'), ' ']);
+    }
+    rows
+}
+
+/// Write out a scrambled mask the way the puzzle does, with its letters in a random order.
+fn shuffled_segments<R: Rng>(rng: &mut R, mask: SegmentMask) -> String {
+    let mut letters : Vec<char> = bits_to_segments(mask).chars().collect();
+    letters.shuffle(rng);
+    letters.into_iter().collect()
+}
+
+/// Make up an observation line for a display with a random wiring showing `target` (which
+/// must fit in four digits): all ten digits in a random order, then the four outputs.  The
+/// wiring used comes back too, so we know the right answer.
+pub fn generate_observation<R: Rng>(rng: &mut R, target: u32) -> (String, Wiring) {
+    assert!(target < 10000, "Only four digits to show {} on", target);
+    let shown : Vec<Digit> = format!("{:04}", target).bytes().map(|b| b - b'0').collect();
+    generate_observation_for(rng, &DisplayType::seven_segment(), &shown)
+}
+
+/// Make up an observation line for any type of display with a random wiring: every glyph in a
+/// random order, then the `shown` glyphs as the outputs.
+pub fn generate_observation_for<R: Rng>(rng: &mut R, display: &DisplayType, shown: &[Digit]) -> (String, Wiring) {
+    let mut wiring : Wiring = (0..display.num_segments as u8).collect();
+    wiring.shuffle(rng);
+
+    let mut glyphs : Vec<usize> = (0..display.glyphs.len()).collect();
+    glyphs.shuffle(rng);
+    let inputs : Vec<String> = glyphs.iter().map(|&g| shuffled_segments(rng, scramble(&wiring, display.glyphs[g].1))).collect();
+    let outputs : Vec<String> = shown.iter()
+        .map(|&g| shuffled_segments(rng, scramble(&wiring, display.glyphs[g as usize].1)))
+        .collect();
+    (format!("{} | {}", inputs.join(" "), outputs.join(" ")), wiring)
+}
+
+/// Decode `count` generated observations of four glyphs from `seed`, returning how many didn't
+/// come back with the wiring and glyphs they were generated from.
+fn stress_test(display: &DisplayType, seed: u64, count: u64) -> u64 {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut failures = 0;
+    for _ in 0..count {
+        let shown : Vec<Digit> = (0..4).map(|_| rng.gen_range(0..display.glyphs.len()) as Digit).collect();
+        let (line, wiring) = generate_observation_for(&mut rng, display, &shown);
+        let mut observation = DisplayObservation::parse_with(display.clone(), &line).unwrap();
+        let decoded = observation.compute_mapping();
+        if decoded != Ok(vec![wiring]) || observation.outputs != shown {
+            eprintln!("Failed to decode {:?} from '{}': {:?}", shown, line, decoded);
+            failures += 1;
+        }
+    }
+    failures
+}
+
 fn main() -> io::Result<()> {
+    // Usage: day8                                 - solve input.txt
+    //        day8 generate SEED COUNT TARGET [SEGMENTS]
+    //                                             - print COUNT random observation lines showing
+    //                                               TARGET, a number up to four digits
+    //        day8 stress SEED COUNT [SEGMENTS]    - check we decode COUNT random observations
+    //        day8 render [FILE]                   - draw the scrambled and corrected outputs
+    // where SEGMENTS is 7 (the default), 14 or 16.
+    let args : Vec<String> = std::env::args().collect();
+    if args.get(1).map(|x| x.as_str()) == Some("render") {
+        let reader = BufReader::new(File::open(args.get(2).map(|x| x.as_str()).unwrap_or("input.txt"))?);
+        for (line_number, line) in reader.lines().enumerate() {
+            let mut observation : DisplayObservation = match line?.parse() {
+                Ok(observation) => observation,
+                Err(err) => { eprintln!("Line {}: {}", line_number + 1, err); continue; }
+            };
+            if let Err(err) = observation.compute_mapping() { eprintln!("Line {}: {}", line_number + 1, err); continue; }
+            match observation.render_outputs() {
+                Some(art) => println!("Line {}: {}\n{}\n", line_number + 1, observation.output_text(), art),
+                None => eprintln!("Line {}: ambiguous, nothing to draw", line_number + 1),
+            }
+        }
+        return Ok(());
+    }
+    if args.len() > 1 {
+        let number = |i: usize| args.get(i).and_then(|x| x.parse::<u64>().ok())
+            .unwrap_or_else(|| panic!("Expected SEED and COUNT after '{}'", args[1]));
+        let (seed, count) = (number(2), number(3));
+        let segments_arg = if args[1] == "generate" { 5 } else { 4 };
+        let display = match args.get(segments_arg).map(|x| x.as_str()).unwrap_or("7") {
+            "7" => DisplayType::seven_segment(),
+            "14" => DisplayType::fourteen_segment(),
+            "16" => DisplayType::sixteen_segment(),
+            other => panic!("No {} segment display", other),
+        };
+        match args[1].as_str() {
+            "generate" => {
+                let target = args.get(4).and_then(|x| x.parse::<u32>().ok()).filter(|&t| t < 10000)
+                    .unwrap_or_else(|| panic!("Expected a TARGET of up to four digits after SEED and COUNT"));
+                let shown : Vec<Digit> = format!("{:04}", target).chars()
+                    .map(|c| display.glyph_for(c).expect("Every display can show digits"))
+                    .collect();
+                let mut rng = StdRng::seed_from_u64(seed);
+                for _ in 0..count {
+                    println!("{}", generate_observation_for(&mut rng, &display, &shown).0);
+                }
+            },
+            "stress" => println!("{} of {} observations failed to decode", stress_test(&display, seed, count), count),
+            other => panic!("Unknown command '{}'", other),
+        }
+        return Ok(());
+    }
+
     // Open the file
     let file = File::open("input.txt").unwrap();
     let reader = BufReader::new(file);
 
-    // Accumulate count and sum as we parse through the inputs.
+    // Accumulate count and sum as we parse through the inputs, skipping any we can't decode.
     let mut count1478: u64 = 0;
-    let mut sum: u32 = 0;
-    for line in reader.lines() {
-        let mut observation : DisplayObservation = line.unwrap().parse().unwrap();
-        observation.compute_mapping();
-        for (i, digit) in observation.outputs.iter().enumerate() {
+    let mut sum: u64 = 0;
+    for (line_number, line) in reader.lines().enumerate() {
+        let mut observation : DisplayObservation = match line?.parse() {
+            Ok(observation) => observation,
+            Err(err) => { eprintln!("Line {}: {}", line_number + 1, err); continue; }
+        };
+        match observation.compute_mapping() {
+            Err(err) => { eprintln!("Line {}: {}", line_number + 1, err); continue; }
+            Ok(wirings) if wirings.len() > 1 => {
+                eprintln!("Line {}: ambiguous, {} wirings fit", line_number + 1, wirings.len());
+                continue;
+            }
+            Ok(_) => { }
+        }
+        for digit in &observation.outputs {
             if *digit == 1 || *digit == 4 || *digit == 7 || *digit == 8 { count1478 += 1; }
-            sum += (*digit as u32) * 10u32.pow(3-i as u32);
         }
+        sum += observation.outputs.iter().fold(0, |acc, digit| acc * 10 + *digit as u64);
     }
 
     println!("Count of 1,4,7,8 is {}.  Sum of outputs is {}.", count1478, sum);
@@ -133,8 +542,139 @@ mod test {
     #[test]
     fn test_example() {
         let mut observation: DisplayObservation = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf".parse().unwrap();
-        observation.compute_mapping();
+        observation.compute_mapping().unwrap();
         assert_eq!(observation.inputs, vec![8,5,2,3,7,9,6,4,0,1]);
         assert_eq!(observation.outputs, vec![5,3,5,3]);
     }
+
+    #[test]
+    fn test_unscrambled() {
+        // With all ten digits on a correctly wired display, the only answer is no scrambling
+        let display = DisplayType::seven_segment();
+        let glyphs : Vec<SegmentMask> = display.glyphs.iter().map(|&(_, g)| g).collect();
+        let wirings = solve_wirings(&display, &glyphs);
+        assert_eq!(wirings, vec![vec![0, 1, 2, 3, 4, 5, 6]]);
+    }
+
+    #[test]
+    fn test_partial_observations() {
+        let masks = |s: &str| s.split_whitespace().map(|x| segments_to_bits(x, 7).unwrap()).collect::<Vec<_>>();
+        // Just the outputs from the example - we know two different five segment digits
+        // alternate, but not which
+        let observation = DisplayObservation::from_masks(vec![], masks("cdfeb fcadb cdfeb cdbaf"));
+        assert!(observation.possible_wirings().len() > 1);
+        assert_eq!(observation.possible_outputs(),
+                   vec![vec![2, 3, 2, 3], vec![3, 2, 3, 2], vec![3, 5, 3, 5], vec![5, 3, 5, 3]]);
+
+        // Seeing only a 1 and an 8 can't tell us which of the 1's wires is which
+        let mut observation = DisplayObservation::from_masks(masks("ab abcdefg"), masks("ab"));
+        let wirings = observation.compute_mapping().unwrap();
+        assert_eq!(wirings.len(), 2 * 120);
+        assert!(observation.outputs.is_empty());
+        assert_eq!(observation.possible_outputs(), vec![vec![1]]);
+
+        // Five segments lit can be 2, 3 or 5
+        let observation = DisplayObservation::from_masks(vec![], masks("abcde"));
+        assert_eq!(observation.possible_outputs(), vec![vec![2], vec![3], vec![5]]);
+
+        // Six of the ten digits is enough to pin it down
+        let mut observation = DisplayObservation::from_masks(masks("acedgfb dab eafb ab cdfbe gcdfa"), masks("cdfeb fcadb"));
+        assert_eq!(observation.compute_mapping().unwrap().len(), 1);
+        assert_eq!(observation.outputs, vec![5, 3]);
+
+        // And a pattern that can't be any digit means nothing fits
+        assert!(solve_wirings(&DisplayType::seven_segment(), &masks("abcdefg a")).is_empty());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let parse = |s: &str| s.parse::<DisplayObservation>().map(|_| ());
+        let decode = |s: &str| s.parse::<DisplayObservation>().unwrap().compute_mapping().map(|_| ());
+        assert_eq!(parse("ab abc | ab"), Ok(()));
+        assert_eq!(parse("ab abc ab"), Err(DecodeError::MissingSeparator));
+        assert_eq!(parse("ab | abc | ab"), Err(DecodeError::MissingSeparator));
+        assert_eq!(parse("ab aBc | ab"), Err(DecodeError::BadToken("aBc".to_string())));
+        assert_eq!(parse("ab abh | ab"), Err(DecodeError::BadToken("abh".to_string())));
+        assert_eq!(parse("ab aba | ab"), Err(DecodeError::BadToken("aba".to_string())));
+        assert_eq!(parse("ab abc | ab 5"), Err(DecodeError::BadToken("5".to_string())));
+        assert_eq!(parse("ab abc ba | ab"), Err(DecodeError::DuplicatePattern("ba".to_string())));
+        assert_eq!(parse("a b c d e f g ab ac ad ae | ab"), Err(DecodeError::TooManyInputs(11)));
+        assert_eq!(decode("ab abc | a"), Err(DecodeError::NoConsistentMapping));
+        assert_eq!(decode("ab abcd | ab ac"), Err(DecodeError::NoConsistentMapping));
+        assert_eq!(decode("acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfe cdbaf"),
+                   Err(DecodeError::OutputNotAmongInputs("cdef".to_string())));
+        assert_eq!(DecodeError::OutputNotAmongInputs("cdef".to_string()).to_string(),
+                   "output pattern 'cdef' isn't among the inputs");
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let (line, wiring) = generate_observation(&mut rng, 42);
+        let parts : Vec<&str> = line.split(' ').collect();
+        assert_eq!(parts.len(), 15);
+        assert_eq!(parts[10], "|");
+        let mut observation : DisplayObservation = line.parse().unwrap();
+        assert_eq!(observation.compute_mapping(), Ok(vec![wiring]));
+        assert_eq!(observation.outputs, vec![0, 0, 4, 2]);
+        let mut inputs = observation.inputs.clone();
+        inputs.sort_unstable();
+        assert_eq!(inputs, (0..10).collect::<Vec<Digit>>());
+
+        // The same seed makes the same line
+        assert_eq!(generate_observation(&mut StdRng::seed_from_u64(8), 42).0, line);
+        assert_eq!(stress_test(&DisplayType::seven_segment(), 2021, 2000), 0);
+    }
+
+    #[test]
+    fn test_alphanumeric_displays() {
+        for display in [DisplayType::seven_segment(), DisplayType::fourteen_segment(), DisplayType::sixteen_segment()] {
+            // Every glyph is different, and uses only segments the display has
+            let mut masks : Vec<SegmentMask> = display.glyphs.iter().map(|&(_, g)| g).collect();
+            masks.sort_unstable();
+            masks.dedup();
+            assert_eq!(masks.len(), display.glyphs.len());
+            assert!(masks.iter().all(|&m| m & !display.all_segments() == 0));
+
+            // A correctly wired display decodes to no scrambling at all
+            assert_eq!(solve_wirings(&display, &masks), vec![(0..display.num_segments as u8).collect::<Wiring>()]);
+            assert_eq!(stress_test(&display, 16, 20), 0);
+        }
+
+        let display = DisplayType::fourteen_segment();
+        let shown : Vec<Digit> = "HI 2U".chars().filter(|&c| c != ' ')
+            .map(|c| display.glyph_for(c).unwrap()).collect();
+        let (line, _) = generate_observation_for(&mut StdRng::seed_from_u64(14), &display, &shown);
+        let mut observation = DisplayObservation::parse_with(display.clone(), &line).unwrap();
+        assert_eq!(observation.compute_mapping().unwrap().len(), 1);
+        assert_eq!(observation.output_text(), "HI2U");
+        assert_eq!(display.glyph_for('~'), None);
+
+        // Letters beyond the display's segments are bad tokens
+        assert_eq!(DisplayObservation::parse_with(display, "abo | ab").unwrap_err(), DecodeError::BadToken("abo".to_string()));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut observation : DisplayObservation = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf".parse().unwrap();
+        assert_eq!(observation.render_outputs(), None);
+        observation.compute_mapping().unwrap();
+        let art = observation.render_outputs().unwrap();
+        let lines : Vec<&str> = art.lines().collect();
+        assert_eq!(lines.len(), 3);
+        // The corrected side reads 5353
+        assert!(lines[0].ends_with(" _   _   _   _  "));
+        assert!(lines[1].ends_with("|_   _| |_   _| "));
+        assert!(lines[2].ends_with(" _|  _|  _|  _| "));
+        // cdfeb lights the wires c, d, f, e and b, then fcadb lights a too
+        assert!(lines[0].starts_with("     _  "));
+        assert!(lines[1].starts_with("|_| "));
+        assert!(lines[2].starts_with("| | "));
+
+        let display = DisplayType::fourteen_segment();
+        let glyphs : Vec<SegmentMask> = display.glyphs.iter().map(|&(_, g)| g).collect();
+        let mut observation = DisplayObservation::new(display, glyphs.clone(), glyphs[..4].to_vec());
+        observation.compute_mapping().unwrap();
+        assert_eq!(observation.render_outputs(), None);
+    }
 }
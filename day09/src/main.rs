@@ -1,105 +1,504 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
 use std::fs::File;
-use std::io::{self, prelude::*, BufReader};
-
-// Get Some(value) for neighboring squares, returning None for those out of bounds
-pub fn neighbors<T: Clone>(data: &Vec<Vec<T>>, row: usize, col: usize) -> [Option<T>; 4] {
-    let nullset: Vec<T> = vec![];
-    [
-        if row > 0 { data.get(row-1).unwrap_or(&nullset).get(col).cloned() } else { None },
-        data.get(row+1).unwrap_or(&nullset).get(col).cloned(),
-        if col > 0 { data.get(row).unwrap_or(&nullset).get(col-1).cloned() } else { None },
-        data.get(row).unwrap_or(&nullset).get(col+1).cloned(),
-    ]
+use std::io::{self, prelude::*};
+use std::str::FromStr;
+
+/// A (row, col) position in a grid
+pub type Cell = (usize, usize);
+
+/// A rectangle of values stored a row at a time in one Vec, indexed by (row, col).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grid<T> {
+    num_rows: usize,
+    num_cols: usize,
+    cells: Vec<T>,
+}
+
+/// Everything that can go wrong parsing a grid of digits.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GridError {
+    /// Something other than 0-9 at (row, col)
+    BadDigit(Cell, char),
+    /// A row that's not as long as the first one
+    Ragged { row: usize, expected: usize, found: usize },
+}
+
+impl std::fmt::Display for GridError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GridError::BadDigit((row, col), c) => write!(f, "'{}' at row {}, column {} isn't a digit", c, row + 1, col + 1),
+            GridError::Ragged { row, expected, found } =>
+                write!(f, "row {} is {} long, but the first row is {}", row + 1, found, expected),
+        }
+    }
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(num_rows: usize, num_cols: usize, value: T) -> Self {
+        Grid { num_rows, num_cols, cells: vec![value; num_rows * num_cols] }
+    }
+
+    /// Copy the grid back out as a Vec per row, the inverse of from_rows.
+    pub fn to_rows(&self) -> Vec<Vec<T>> {
+        self.rows().map(|row| row.to_vec()).collect()
+    }
+}
+
+impl<T> Grid<T> {
+    /// Build a grid from its rows, which must all be the same length.
+    pub fn from_rows(rows: Vec<Vec<T>>) -> Self {
+        let num_rows = rows.len();
+        let num_cols = rows.first().map_or(0, |row| row.len());
+        assert!(rows.iter().all(|row| row.len() == num_cols), "Grid rows must all be the same length");
+        Grid { num_rows, num_cols, cells: rows.into_iter().flatten().collect() }
+    }
+
+    pub fn num_rows(&self) -> usize { self.num_rows }
+    pub fn num_cols(&self) -> usize { self.num_cols }
+
+    fn index_of(&self, (row, col): Cell) -> Option<usize> {
+        if row < self.num_rows && col < self.num_cols { Some(row * self.num_cols + col) } else { None }
+    }
+
+    /// The value at a cell, or None if it's off the grid.
+    pub fn get(&self, cell: Cell) -> Option<&T> {
+        self.index_of(cell).map(|i| &self.cells[i])
+    }
+
+    pub fn get_mut(&mut self, cell: Cell) -> Option<&mut T> {
+        self.index_of(cell).map(|i| &mut self.cells[i])
+    }
+
+    /// The cells up, down, left and right of a cell (in that order) that are on the grid.
+    pub fn neighbors4(&self, (row, col): Cell) -> impl Iterator<Item = Cell> {
+        let (num_rows, num_cols) = (self.num_rows, self.num_cols);
+        [
+            row.checked_sub(1).map(|r| (r, col)),
+            Some((row + 1, col)).filter(|&(r, _)| r < num_rows),
+            col.checked_sub(1).map(|c| (row, c)),
+            Some((row, col + 1)).filter(|&(_, c)| c < num_cols),
+        ].into_iter().flatten()
+    }
+
+    /// All eight cells around a cell that are on the grid, diagonals included, reading across
+    /// then down.
+    pub fn neighbors8(&self, (row, col): Cell) -> impl Iterator<Item = Cell> {
+        let (num_rows, num_cols) = (self.num_rows, self.num_cols);
+        (row.saturating_sub(1)..(row + 2).min(num_rows))
+            .flat_map(move |r| (col.saturating_sub(1)..(col + 2).min(num_cols)).map(move |c| (r, c)))
+            .filter(move |&cell| cell != (row, col))
+    }
+
+    /// The values in neighbors4() order.
+    pub fn neighbor_values4(&self, cell: Cell) -> impl Iterator<Item = &T> {
+        self.neighbors4(cell).map(move |n| &self[n])
+    }
+
+    /// The values in neighbors8() order.
+    pub fn neighbor_values8(&self, cell: Cell) -> impl Iterator<Item = &T> {
+        self.neighbors8(cell).map(move |n| &self[n])
+    }
+
+    /// Every cell with its value, a row at a time.
+    pub fn cells(&self) -> impl Iterator<Item = (Cell, &T)> {
+        let num_cols = self.num_cols;
+        self.cells.iter().enumerate().map(move |(i, value)| ((i / num_cols, i % num_cols), value))
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.cells.chunks(self.num_cols.max(1))
+    }
+
+    pub fn column(&self, col: usize) -> impl Iterator<Item = &T> {
+        assert!(col < self.num_cols, "Column {} is off the grid", col);
+        self.cells.iter().skip(col).step_by(self.num_cols)
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &T>> {
+        (0..self.num_cols).map(move |col| self.column(col))
+    }
+
+    /// A grid the same shape, with `f` applied to every value.
+    pub fn map<U, F: FnMut(&T) -> U>(&self, f: F) -> Grid<U> {
+        Grid { num_rows: self.num_rows, num_cols: self.num_cols, cells: self.cells.iter().map(f).collect() }
+    }
+}
+
+impl<T> std::ops::Index<Cell> for Grid<T> {
+    type Output = T;
+    fn index(&self, cell: Cell) -> &T {
+        self.get(cell).unwrap_or_else(|| panic!("{:?} is off the grid", cell))
+    }
+}
+
+impl<T> std::ops::IndexMut<Cell> for Grid<T> {
+    fn index_mut(&mut self, cell: Cell) -> &mut T {
+        self.get_mut(cell).unwrap_or_else(|| panic!("{:?} is off the grid", cell))
+    }
 }
 
+/// Lines of digits, one cell each, like the puzzle input.
+impl FromStr for Grid<u8> {
+    type Err = GridError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rows = Vec::new();
+        for (row, line) in s.lines().enumerate() {
+            let digits = line.chars().enumerate()
+                .map(|(col, c)| c.to_digit(10).map(|d| d as u8).ok_or(GridError::BadDigit((row, col), c)))
+                .collect::<Result<Vec<u8>, _>>()?;
+            if let Some(first) = rows.first().map(|first: &Vec<u8>| first.len()).filter(|&len| len != digits.len()) {
+                return Err(GridError::Ragged { row, expected: first, found: digits.len() });
+            }
+            rows.push(digits);
+        }
+        Ok(Grid::from_rows(rows))
+    }
+}
+
+/// The water a basin holds once it's rained enough to fill it to the brim.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BasinWater {
+    /// Total depth of water over all the basin's cells
+    pub volume: u64,
+    /// The cell the water overflows through once it's full, None if the basin stays dry
+    pub spill: Option<Cell>,
+}
 
 pub struct HeightMap {
-    heights: Vec<Vec<u8>>
+    heights: Grid<u8>,
+    /// Cells this high or higher are walls between basins, and belong to none of them.  None
+    /// means there are no walls, and everything drains somewhere.
+    wall_height: Option<u8>,
 }
 
 impl HeightMap {
     pub fn from_file(filename : &str) -> Self {
-        let file = File::open(filename).unwrap();
-        let reader = BufReader::new(file);
-        let mut heights: Vec<Vec<u8>> = Vec::new();
-        for line in reader.lines() {
-            heights.push(line
-                .unwrap()
-                .bytes()
-                .map(|x| x-('0' as u8))
-                .collect()
-            );
-        }
-        HeightMap { heights }
+        let text = std::fs::read_to_string(filename).unwrap();
+        let heights: Grid<u8> = text.parse().unwrap_or_else(|err| panic!("Bad height map {}: {}", filename, err));
+        HeightMap::from_grid(heights)
     }
 
     pub fn from_vec(heights : Vec<Vec<u8>>) -> Self {
-        HeightMap { heights }
-    }
-
-    pub fn find_low_points(&self) -> Vec<(usize, usize)> {
-        let mut low_points = Vec::new();
-        for row in 0..self.heights.len() {
-            for col in 0..self.heights[row].len() {
-                let value = self.heights[row][col];
-                if neighbors(&self.heights, row, col).iter().all(
-                    |x| x.is_none() || x.unwrap() > value
-                ) {
-                    low_points.push((row, col));
-                }
-            }
-        }
-        low_points
+        HeightMap::from_grid(Grid::from_rows(heights))
+    }
+
+    pub fn from_grid(heights: Grid<u8>) -> Self {
+        HeightMap { heights, wall_height: Some(9) }
+    }
+
+    /// Use a different wall height than the puzzle's 9, or no walls at all.
+    pub fn with_wall_height(mut self, wall_height: Option<u8>) -> Self {
+        self.wall_height = wall_height;
+        self
     }
 
+    fn is_wall(&self, cell: Cell) -> bool {
+        self.wall_height.is_some_and(|wall| self.heights[cell] >= wall)
+    }
+
+    pub fn find_low_points(&self) -> Vec<Cell> {
+        self.heights.cells()
+            .filter(|&(cell, value)| self.heights.neighbor_values4(cell).all(|x| x > value))
+            .map(|(cell, _)| cell)
+            .collect()
+    }
+
+    /// The size of each basin, and which basin (if any) each cell is in, row by row.
     pub fn basin_map(&self) -> (Vec<u32>, Vec<Vec<Option<u32>>>) {
+        let (basin_sizes, basins) = self.basin_map_grid();
+        (basin_sizes, basins.to_rows())
+    }
+
+    /// basin_map(), with the labelling as a Grid.
+    pub fn basin_map_grid(&self) -> (Vec<u32>, Grid<Option<u32>>) {
         let low_points = self.find_low_points();
         // Create an empty basin map, then populate the low_points with unique IDs
-        let mut basins = vec![vec![None; self.heights[0].len()]; self.heights.len()];
-        for (i, (x,y)) in low_points.iter().enumerate() {
-            basins[*x][*y] = Some(i as u32);
+        let mut basins = self.heights.map(|_| None);
+        for (i, cell) in low_points.iter().enumerate() {
+            basins[*cell] = Some(i as u32);
         }
         let mut basin_sizes: Vec<u32> = vec![1; low_points.len()];
 
-        // Now iterate through the map, over and over, until we stop growing the basins.  This is
-        // a pretty dumb way to do this.  We should probably grow out from the seeds.
-        loop {
-            let mut filled_something_new = false;
-            for row in 0..basins.len() {
-                for col in 0..basins[0].len() {
-                    if self.heights[row][col] != 9 && basins[row][col].is_none() {
-                        for neighbor in neighbors(&basins, row, col) {
-                            if neighbor.is_some() && neighbor.unwrap().is_some() {
-                                basins[row][col] = neighbor.unwrap();
-                                basin_sizes[neighbor.unwrap().unwrap() as usize] += 1;
-                                filled_something_new = true;
-                                break;
-                            }
-                        }
+        // Grow all the basins out from their low points at once, a ring at a time, so each cell
+        // is visited once and goes to whichever basin reaches it first.
+        let mut frontier: VecDeque<Cell> = low_points.into_iter().collect();
+        while let Some(cell) = frontier.pop_front() {
+            let basin = basins[cell];
+            for next in self.heights.neighbors4(cell) {
+                if !self.is_wall(next) && basins[next].is_none() {
+                    basins[next] = basin;
+                    basin_sizes[basin.unwrap() as usize] += 1;
+                    frontier.push_back(next);
+                }
+            }
+        }
+        (basin_sizes, basins)
+    }
+
+    /// Split the map into drainage basins.  Every cell drains to its lowest neighbour, if that's
+    /// lower than it is, picking the first of equally low neighbours in neighbors4() order (up,
+    /// down, left, right).  Flat areas drain towards their nearest edge that goes downhill, and
+    /// a flat area with no way down at all is a single minimum, so gets one basin however big it
+    /// is.  Walls don't drain and aren't drained into.  Basins are numbered in the order their
+    /// minima turn up reading the map row by row, and come back like basin_map()'s.
+    pub fn watershed(&self) -> (Vec<u32>, Vec<Vec<Option<u32>>>) {
+        let (basin_sizes, basins) = self.watershed_grid();
+        (basin_sizes, basins.to_rows())
+    }
+
+    /// watershed(), with the labelling as a Grid.
+    pub fn watershed_grid(&self) -> (Vec<u32>, Grid<Option<u32>>) {
+        let heights = &self.heights;
+
+        // Where each cell drains to, if it has somewhere lower to go
+        let mut drains_to: Grid<Option<Cell>> = heights.map(|_| None);
+        for (cell, &height) in heights.cells() {
+            if self.is_wall(cell) { continue; }
+            drains_to[cell] = heights.neighbors4(cell)
+                .filter(|&next| !self.is_wall(next) && heights[next] < height)
+                .fold(None, |lowest, next| match lowest {
+                    Some(low) if heights[low] <= heights[next] => Some(low),
+                    _ => Some(next),
+                });
+        }
+
+        // Then sort out each flat area, growing out from the cells around its edge that drain
+        // somewhere so everything else drains to a neighbour one step nearer the way out.
+        let mut visited = heights.map(|_| false);
+        let mut minima: Vec<Vec<Cell>> = Vec::new();
+        for (start, &level) in heights.cells() {
+            if visited[start] || self.is_wall(start) { continue; }
+            let mut flat = vec![start];
+            visited[start] = true;
+            let mut i = 0;
+            while i < flat.len() {
+                for next in heights.neighbors4(flat[i]) {
+                    if !visited[next] && !self.is_wall(next) && heights[next] == level {
+                        visited[next] = true;
+                        flat.push(next);
                     }
-                }   
+                }
+                i += 1;
             }
-            if !filled_something_new { break; }
+            flat.sort_unstable();
+
+            let mut frontier: VecDeque<Cell> = flat.iter().copied().filter(|&cell| drains_to[cell].is_some()).collect();
+            if frontier.is_empty() {
+                minima.push(flat);
+                continue;
+            }
+            while let Some(cell) = frontier.pop_front() {
+                for next in heights.neighbors4(cell) {
+                    if heights[next] == level && !self.is_wall(next) && drains_to[next].is_none() {
+                        drains_to[next] = Some(cell);
+                        frontier.push_back(next);
+                    }
+                }
+            }
+        }
+
+        // Label the minima, then follow each cell downhill until we hit something labelled
+        let mut basins = heights.map(|_| None);
+        let mut basin_sizes = vec![0; minima.len()];
+        for (id, flat) in minima.iter().enumerate() {
+            for &cell in flat { basins[cell] = Some(id as u32); }
+        }
+        for (start, _) in heights.cells() {
+            if self.is_wall(start) { continue; }
+            let mut path = Vec::new();
+            let mut cell = start;
+            while basins[cell].is_none() {
+                path.push(cell);
+                cell = drains_to[cell].unwrap();
+            }
+            let basin = basins[cell];
+            for step in path { basins[step] = basin; }
+            basin_sizes[basin.unwrap() as usize] += 1;
         }
         (basin_sizes, basins)
     }
 
+    /// How high water settles over each cell if it rains forever - the lowest height water can
+    /// get out over to the edge of the map - along with, for cells that end up underwater,
+    /// the dry cell it overflows through.  We flood in from the border, always taking the
+    /// lowest cell we've reached next, so whatever's reached from it fills up to its level.
+    fn water_levels(&self) -> (Grid<u8>, Grid<Option<Cell>>) {
+        let (num_rows, num_cols) = (self.heights.num_rows(), self.heights.num_cols());
+        let mut levels = self.heights.clone();
+        let mut outlets = self.heights.map(|_| None);
+        let mut reached = self.heights.map(|_| false);
+        let mut queue = BinaryHeap::new();
+        for ((row, col), &height) in self.heights.cells() {
+            if row == 0 || col == 0 || row == num_rows - 1 || col == num_cols - 1 {
+                reached[(row, col)] = true;
+                queue.push(Reverse((height, (row, col))));
+            }
+        }
+        while let Some(Reverse((level, cell))) = queue.pop() {
+            for next in self.heights.neighbors4(cell) {
+                if reached[next] { continue; }
+                reached[next] = true;
+                if self.heights[next] < level {
+                    levels[next] = level;
+                    outlets[next] = outlets[cell].or(Some(cell));
+                }
+                queue.push(Reverse((levels[next], next)));
+            }
+        }
+        (levels, outlets)
+    }
+
+    /// Fill every basin from watershed() to the point it spills over, and say how much water
+    /// each holds and where it overflows.  If a basin has more than one pool in it, the spill
+    /// is the one for the pool that reaches furthest down.
+    pub fn rain_trap(&self) -> Vec<BasinWater> {
+        let (levels, outlets) = self.water_levels();
+        let (sizes, basins) = self.watershed_grid();
+        let mut water: Vec<BasinWater> = sizes.iter().map(|_| BasinWater { volume: 0, spill: None }).collect();
+        let mut deepest = vec![u8::MAX; sizes.len()];
+        for (cell, basin) in basins.cells() {
+            let height = self.heights[cell];
+            if let (Some(basin), Some(outlet)) = (basin, outlets[cell]) {
+                let basin = *basin as usize;
+                water[basin].volume += (levels[cell] - height) as u64;
+                if height < deepest[basin] {
+                    deepest[basin] = height;
+                    water[basin].spill = Some(outlet);
+                }
+            }
+        }
+        water
+    }
+
+    /// Write the heights out as a binary greyscale PGM, one pixel per cell, lowest darkest.
+    pub fn write_pgm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let max_height = self.heights.cells().map(|(_, &h)| h).max().unwrap_or(0).max(1);
+        write!(out, "P5\n{} {}\n{}\n", self.heights.num_cols(), self.heights.num_rows(), max_height)?;
+        for row in self.heights.rows() {
+            out.write_all(row)?;
+        }
+        Ok(())
+    }
+
+    // Which cells are low points, so drawing can look them up rather than search the list.
+    fn low_point_grid(&self) -> Grid<bool> {
+        let mut low_points = self.heights.map(|_| false);
+        for cell in self.find_low_points() {
+            low_points[cell] = true;
+        }
+        low_points
+    }
+
+    // The colour of each cell when drawing a basin labelling - each basin gets its own colour,
+    // walls and cells in no basin are black and grey, and low points show up white.
+    fn basin_colours(&self, basins: &Grid<Option<u32>>, low_points: &Grid<bool>) -> Grid<[u8; 3]> {
+        let mut colours = Grid::new(basins.num_rows(), basins.num_cols(), [96, 96, 96]);
+        for (cell, basin) in basins.cells() {
+            match basin {
+                _ if low_points[cell] => colours[cell] = [255, 255, 255],
+                Some(id) => colours[cell] = basin_colour(*id),
+                None if self.is_wall(cell) => colours[cell] = [0, 0, 0],
+                None => { }
+            }
+        }
+        colours
+    }
+
+    /// Write a basin labelling (from basin_map_grid() or watershed_grid()) out as a binary
+    /// colour PPM.
+    pub fn write_ppm<W: Write>(&self, basins: &Grid<Option<u32>>, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.heights.num_cols(), self.heights.num_rows())?;
+        for row in self.basin_colours(basins, &self.low_point_grid()).rows() {
+            out.write_all(&row.concat())?;
+        }
+        Ok(())
+    }
+
+    /// Draw a basin labelling for a terminal - each cell's height on its basin's colour, with
+    /// low points in bold.
+    pub fn render_ansi(&self, basins: &Grid<Option<u32>>) -> String {
+        let low_points = self.low_point_grid();
+        let mut rendered = String::new();
+        for (cell, [r, g, b]) in self.basin_colours(basins, &low_points).cells() {
+            let bold = if low_points[cell] { "1;" } else { "" };
+            rendered += &format!("\x1b[{}38;2;0;0;0;48;2;{};{};{}m{}", bold, r, g, b, self.heights[cell]);
+            if cell.1 == self.heights.num_cols() - 1 {
+                rendered += "\x1b[0m\n";
+            }
+        }
+        rendered
+    }
+
     pub fn sum_of_risks(&self) -> u32 {
-        self.find_low_points().iter().map(|(x,y)| self.heights[*x][*y] as u32 + 1).sum()
+        self.find_low_points().iter().map(|&cell| self.heights[cell] as u32 + 1).sum()
     }
 }
 
+/// A bright colour for a basin, walking round the colour wheel by the golden angle so basins
+/// with nearby IDs (which are usually next to each other) look different.
+fn basin_colour(id: u32) -> [u8; 3] {
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    // Keep away from white and black, so low points and walls stand out
+    let scale = |c: f64| (48.0 + c * 160.0) as u8;
+    [scale(r), scale(g), scale(b)]
+}
+
 fn main() -> io::Result<()> {
-    let height_map = HeightMap::from_file("input.txt");
+    // Usage: day9 [--wall HEIGHT|none] [--watershed] [--rain] [--pgm FILE] [--ppm FILE] [--ansi]
+    let args : Vec<String> = std::env::args().collect();
+    let mut height_map = HeightMap::from_file("input.txt");
+    if let Some(i) = args.iter().position(|x| x == "--wall") {
+        let wall = args.get(i + 1).expect("Expected a height or 'none' after --wall");
+        height_map = height_map.with_wall_height(match wall.as_str() {
+            "none" => None,
+            height => Some(height.parse().expect("Wall height should be a number")),
+        });
+    }
+
     println!("Sum of risks: {}", height_map.sum_of_risks());
     println!("Number of low points: {}", height_map.find_low_points().len());
-    let (sizes, _basins) = height_map.basin_map();
+    let (sizes, basins) = if args.iter().any(|x| x == "--watershed") {
+        height_map.watershed_grid()
+    } else {
+        height_map.basin_map_grid()
+    };
     let mut sorted_sizes = sizes.clone();
     sorted_sizes.sort();
     sorted_sizes.reverse();
     println!("Product of three largest basin areas: {}",
              sorted_sizes[0]*sorted_sizes[1]*sorted_sizes[2]);
 
+    if args.iter().any(|x| x == "--rain") {
+        let water = height_map.rain_trap();
+        println!("Water held when it rains: {}", water.iter().map(|w| w.volume).sum::<u64>());
+        if let Some(fullest) = water.iter().max_by_key(|w| w.volume).filter(|w| w.volume > 0) {
+            println!("Fullest basin holds {}, spilling over at {:?}", fullest.volume, fullest.spill.unwrap());
+        }
+    }
+
+    let flag_value = |flag: &str| args.iter().position(|x| x == flag)
+        .map(|i| args.get(i + 1).unwrap_or_else(|| panic!("Expected a filename after {}", flag)));
+    if let Some(filename) = flag_value("--pgm") {
+        height_map.write_pgm(&mut io::BufWriter::new(File::create(filename)?))?;
+    }
+    if let Some(filename) = flag_value("--ppm") {
+        height_map.write_ppm(&basins, &mut io::BufWriter::new(File::create(filename)?))?;
+    }
+    if args.iter().any(|x| x == "--ansi") {
+        print!("{}", height_map.render_ansi(&basins));
+    }
+
     Ok(())
 }
 
@@ -130,4 +529,127 @@ mod test {
         assert!(sizes.contains(&14));
         assert!(sizes.contains(&3));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_winding_basin() {
+        // A single basin snaking back and forth - the worst case for sweeping the whole map
+        // until nothing changes, but one visit per cell growing out from the low point.
+        let size = 201;
+        let heights = HeightMap::from_vec((0..size).map(|row| (0..size).map(|col| {
+            let open = row % 2 == 0 || (row % 4 == 1 && col == size - 1) || (row % 4 == 3 && col == 0);
+            if row == 0 && col == 0 { 0 } else if open { 5 } else { 9 }
+        }).collect()).collect());
+
+        let (sizes, basins) = heights.basin_map();
+        assert_eq!(sizes, vec![101 * 201 + 100]);
+        assert_eq!(basins[size - 1][size - 1], Some(0));
+        assert_eq!(basins[1][0], None);
+    }
+
+    #[test]
+    fn test_watershed() {
+        // With the usual walls, the example's basins don't change
+        let heights = HeightMap::from_vec(vec![
+            vec![2,1,9,9,9,4,3,2,1,0],
+            vec![3,9,8,7,8,9,4,9,2,1],
+            vec![9,8,5,6,7,8,9,8,9,2],
+            vec![8,7,6,7,8,9,6,7,8,9],
+            vec![9,8,9,9,9,6,5,6,7,8],
+        ]);
+        assert_eq!(heights.watershed(), heights.basin_map());
+
+        // A flat valley has no strict low point, but is still one basin
+        let flat = HeightMap::from_vec(vec![vec![2,2,2], vec![2,2,2]]);
+        assert!(flat.basin_map().0.is_empty());
+        assert_eq!(flat.watershed().0, vec![6]);
+
+        // Ties go to the first of up, down, left and right, including across a flat
+        let tied = HeightMap::from_vec(vec![vec![1,5,1]]);
+        assert_eq!(tied.watershed(), (vec![2, 1], vec![vec![Some(0), Some(0), Some(1)]]));
+        let shelf = HeightMap::from_vec(vec![vec![0,3,3,3,1]]);
+        assert_eq!(shelf.watershed().0, vec![3, 2]);
+
+        // Without walls, the ridge drains into its lowest side
+        let ridge = HeightMap::from_vec(vec![vec![1,2,9,4,3,0]]).with_wall_height(None);
+        assert_eq!(ridge.watershed(), (vec![3, 3], vec![vec![Some(0), Some(0), Some(0), Some(1), Some(1), Some(1)]]));
+        let ridge = ridge.with_wall_height(Some(9));
+        assert_eq!(ridge.watershed().1, vec![vec![Some(0), Some(0), None, Some(1), Some(1), Some(1)]]);
+    }
+
+    #[test]
+    fn test_rain_trap() {
+        let heights = HeightMap::from_vec(vec![
+            vec![1,4,3,1,3,2],
+            vec![3,2,1,3,2,4],
+            vec![2,3,3,2,3,1],
+        ]).with_wall_height(None);
+        let water = heights.rain_trap();
+        assert_eq!(water.iter().map(|w| w.volume).sum::<u64>(), 4);
+        let (_, basins) = heights.watershed();
+        // The two cells in the middle fill up to 3 and spill over the top edge, as does the
+        // single cell on the right
+        let middle = &water[basins[1][2].unwrap() as usize];
+        assert_eq!(middle, &BasinWater { volume: 3, spill: Some((0, 2)) });
+        let right = &water[basins[1][4].unwrap() as usize];
+        assert_eq!(right, &BasinWater { volume: 1, spill: Some((0, 4)) });
+        assert_eq!(water[basins[0][0].unwrap() as usize], BasinWater { volume: 0, spill: None });
+
+        // A bowl fills right up to its rim
+        let bowl = HeightMap::from_vec(vec![vec![5,5,5], vec![5,1,5], vec![5,5,5]]);
+        assert_eq!(bowl.rain_trap(), vec![BasinWater { volume: 4, spill: Some((0, 1)) }]);
+    }
+
+    #[test]
+    fn test_render() {
+        let heights = HeightMap::from_vec(vec![
+            vec![2,1,9],
+            vec![3,9,0],
+        ]);
+        let mut pgm = Vec::new();
+        heights.write_pgm(&mut pgm).unwrap();
+        assert_eq!(pgm, b"P5\n3 2\n9\n\x02\x01\x09\x03\x09\x00");
+
+        let (_, basins) = heights.basin_map_grid();
+        let mut ppm = Vec::new();
+        heights.write_ppm(&basins, &mut ppm).unwrap();
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        let pixels: Vec<&[u8]> = ppm[header.len()..].chunks(3).collect();
+        assert_eq!(pixels.len(), 6);
+        // Low points are white, walls black, and the rest of a basin its own colour
+        assert_eq!(pixels[1], [255, 255, 255]);
+        assert_eq!(pixels[5], [255, 255, 255]);
+        assert_eq!(pixels[2], [0, 0, 0]);
+        assert_eq!(pixels[0], basin_colour(0));
+        assert_eq!(pixels[3], basin_colour(0));
+        assert_ne!(basin_colour(0), basin_colour(1));
+
+        let ansi = heights.render_ansi(&basins);
+        assert_eq!(ansi.lines().count(), 2);
+        assert_eq!(ansi.matches("\x1b[1;").count(), 2);
+        assert!(ansi.lines().all(|line| line.ends_with("\x1b[0m")));
+    }
+
+    #[test]
+    fn test_grid() {
+        let grid: Grid<u8> = "123\n456\n789\n".parse().unwrap();
+        assert_eq!((grid.num_rows(), grid.num_cols()), (3, 3));
+        assert_eq!(grid[(1, 2)], 6);
+        assert_eq!(grid.get((3, 0)), None);
+        assert_eq!(grid.get((0, 3)), None);
+
+        assert_eq!(grid.neighbors4((0, 0)).collect::<Vec<_>>(), vec![(1, 0), (0, 1)]);
+        assert_eq!(grid.neighbor_values4((1, 1)).copied().collect::<Vec<_>>(), vec![2, 8, 4, 6]);
+        assert_eq!(grid.neighbor_values8((1, 1)).copied().collect::<Vec<_>>(), vec![1, 2, 3, 4, 6, 7, 8, 9]);
+        assert_eq!(grid.neighbor_values8((2, 2)).copied().collect::<Vec<_>>(), vec![5, 6, 8]);
+
+        assert_eq!(grid.rows().nth(2), Some(&[7, 8, 9][..]));
+        assert_eq!(Grid::from_rows(grid.to_rows()), grid);
+        assert_eq!(grid.column(1).copied().collect::<Vec<_>>(), vec![2, 5, 8]);
+        assert_eq!(grid.columns().map(|c| c.copied().sum::<u8>()).collect::<Vec<_>>(), vec![12, 15, 18]);
+        assert!(grid.map(|&x| x % 2 == 0)[(0, 1)]);
+
+        assert_eq!("12\n3x".parse::<Grid<u8>>(), Err(GridError::BadDigit((1, 1), 'x')));
+        assert_eq!("12\n345".parse::<Grid<u8>>(), Err(GridError::Ragged { row: 1, expected: 2, found: 3 }));
+    }
+}
@@ -5,22 +5,525 @@ const OPEN : &str = "([{<";
 const CLOSE : &str = ")]}>";
 const ERROR_SCORES : [u64; 4] = [3, 57, 1197, 25137];
 
+/// What counts as a delimiter when validating, and what doesn't count at all.
+#[derive(Clone, Debug, Default)]
+pub struct Grammar {
+    /// Opening and closing delimiters that have to nest properly
+    pairs: Vec<(String, String)>,
+    /// Opening and closing delimiters of regions (strings, comments) where nothing else counts,
+    /// and the character (if any) that means the next one doesn't count either inside them
+    quotes: Vec<(String, String, Option<char>)>,
+    /// Characters that are skipped outside quotes
+    ignorable: Vec<char>,
+    /// Skip anything that isn't a delimiter, rather than calling it corruption
+    ignore_others: bool,
+}
+
+// A delimiter we've found - openers come with the closer they're expecting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token<'g> {
+    Open(&'g str, &'g str),
+    Quote(&'g str, &'g str, Option<char>),
+    Close(&'g str),
+}
+
+impl Grammar {
+    /// The four pairs of brackets from the puzzle, where anything else is corruption.
+    pub fn brackets() -> Self {
+        OPEN.chars().zip(CLOSE.chars())
+            .fold(Grammar::default(), |grammar, (open, close)| grammar.with_pair(&open.to_string(), &close.to_string()))
+    }
+
+    /// Good enough for most C-like source - brackets (but not <>, which are usually less than
+    /// and greater than), block comments, strings and characters with backslash escapes, and
+    /// line comments, ignoring everything else.
+    pub fn source_code() -> Self {
+        Grammar::default()
+            .with_pair("(", ")").with_pair("[", "]").with_pair("{", "}")
+            .with_quote("/*", "*/").with_quote("//", "\n")
+            .with_escaped_quote("\"", "\"", '\\').with_escaped_quote("'", "'", '\\')
+            .ignoring_others()
+    }
+
+    pub fn with_pair(mut self, open: &str, close: &str) -> Self {
+        self.pairs.push((open.to_string(), close.to_string()));
+        self
+    }
+
+    pub fn with_quote(mut self, open: &str, close: &str) -> Self {
+        self.quotes.push((open.to_string(), close.to_string(), None));
+        self
+    }
+
+    /// A quote where `escape` stops the character after it counting, closer included.
+    pub fn with_escaped_quote(mut self, open: &str, close: &str, escape: char) -> Self {
+        self.quotes.push((open.to_string(), close.to_string(), Some(escape)));
+        self
+    }
+
+    // Every delimiter, opening and closing, pairs and quotes alike
+    fn delimiters(&self) -> impl Iterator<Item = &String> {
+        self.pairs.iter().flat_map(|(open, close)| [open, close])
+            .chain(self.quotes.iter().flat_map(|(open, close, _)| [open, close]))
+    }
+
+    pub fn with_ignorable(mut self, ignorable: &str) -> Self {
+        self.ignorable.extend(ignorable.chars());
+        self
+    }
+
+    pub fn ignoring_others(mut self) -> Self {
+        self.ignore_others = true;
+        self
+    }
+
+    // The delimiter at the start of `text`, if there is one, and how long it is.  The longest
+    // one wins, so "/*" isn't mistaken for "/".  Where a delimiter both opens and closes
+    // (like "|"), it closes if that's what we're expecting, and opens otherwise.  Delimiters
+    // that start or end like a word only count as whole words, so "begin" isn't found in
+    // "beginning", which is why we need the character before `text` too.
+    fn token_at(&self, previous: Option<char>, text: &str, expected: Option<&str>) -> Option<(Token<'_>, usize)> {
+        let opens = self.pairs.iter().map(|(open, close)| (open, Token::Open(open, close)));
+        let quotes = self.quotes.iter().map(|(open, close, escape)| (open, Token::Quote(open, close, *escape)));
+        let closes = self.pairs.iter().map(|(_, close)| (close, Token::Close(close)));
+        let priority = |token: &Token| match token {
+            Token::Close(close) if Some(*close) == expected => 3,
+            Token::Quote(..) => 2,
+            Token::Open(..) => 1,
+            Token::Close(_) => 0,
+        };
+        let whole_word = |delimiter: &str| {
+            let joins = |a: Option<char>, b: Option<char>| a.is_some_and(is_word_char) && b.is_some_and(is_word_char);
+            !joins(previous, delimiter.chars().next()) && !joins(delimiter.chars().last(), text[delimiter.len()..].chars().next())
+        };
+        opens.chain(quotes).chain(closes)
+            .filter(|(delimiter, _)| !delimiter.is_empty() && text.starts_with(delimiter.as_str()) && whole_word(delimiter))
+            .max_by_key(|(delimiter, token)| (delimiter.len(), priority(token)))
+            .map(|(delimiter, token)| (token, delimiter.len()))
+    }
+
+    // Whether `text` is the start of a delimiter longer than it, or is a delimiter that ends
+    // like a word, so we can't decide what it is until we see more
+    fn could_be_longer(&self, text: &str) -> bool {
+        self.delimiters()
+            .any(|delimiter| (delimiter.len() > text.len() && delimiter.starts_with(text))
+                || (delimiter == text && delimiter.chars().last().is_some_and(is_word_char)))
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
 // Either returns Ok(stack_of_expected_characters) or Err(first_bad_character)
 fn validate(string_to_validate: &str) -> Result<String, char> {
-    let mut expected = String::with_capacity(128);
-    for char_to_validate in string_to_validate.chars() {
-        if let Some(position) = OPEN.chars().position(|c| c == char_to_validate) {
-            // For every 'opening' character, push the corresponding closing character onto 'stack'
-            expected.push(CLOSE.chars().nth(position).unwrap());
-        } else {
-            // Make sure 'closing' characters match the stack, or we have corruption
-            let should_be = expected.pop();
-            if should_be != Some(char_to_validate) {
-                return Err(char_to_validate);
+    validate_with(&Grammar::brackets(), string_to_validate)
+        .map(|expected| expected.concat())
+        .map_err(|bad| bad.chars().next().unwrap())
+}
+
+// Either returns Ok(stack_of_expected_closers), with the one we need next at the end, or
+// Err(first_bad_delimiter).  An unfinished quote's closer goes on the stack too.
+fn validate_with<'g>(grammar: &'g Grammar, text: &str) -> Result<Vec<&'g str>, String> {
+    match scan(grammar, text) {
+        Ok(unclosed) => Ok(unclosed.iter().map(|u| u.expected).collect()),
+        Err(Diagnostic::Corrupted { found, .. }) => Err(found.text),
+        Err(Diagnostic::Incomplete { .. }) => unreachable!("scan() doesn't call anything incomplete"),
+    }
+}
+
+/// Where something is in the text, counting lines and columns (in chars) from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// An opener that hasn't been closed yet, and the closer it's waiting for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Unclosed<'g> {
+    pub open: &'g str,
+    pub expected: &'g str,
+    pub at: Position,
+}
+
+/// Something that doesn't belong, and where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Found {
+    pub text: String,
+    pub at: Position,
+}
+
+/// What's wrong with some text, with enough detail to point at it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diagnostic<'g> {
+    /// A delimiter that doesn't close the innermost opener (if there is one)
+    Corrupted { found: Found, unclosed: Option<Unclosed<'g>> },
+    /// Every opener still waiting at the end, outermost first
+    Incomplete { unclosed: Vec<Unclosed<'g>> },
+}
+
+// Ok(every opener still waiting to be closed at the end), or Err(Diagnostic::Corrupted) at the
+// first bad delimiter
+fn scan<'g>(grammar: &'g Grammar, text: &str) -> Result<Vec<Unclosed<'g>>, Diagnostic<'g>> {
+    match diagnose(grammar, text) {
+        None => Ok(Vec::new()),
+        Some(Diagnostic::Incomplete { unclosed }) => Ok(unclosed),
+        Some(corrupted) => Err(corrupted),
+    }
+}
+
+/// Check some text, returning None if it's balanced or what's wrong with it.
+fn diagnose<'g>(grammar: &'g Grammar, text: &str) -> Option<Diagnostic<'g>> {
+    let mut validator = Validator::new(grammar);
+    validator.feed(text);
+    validator.status()
+}
+
+/// Validates text a bit at a time as it comes in, so we never need all of it at once.  Clone
+/// it to keep a snapshot to come back to.
+#[derive(Clone, Debug)]
+pub struct Validator<'g> {
+    grammar: &'g Grammar,
+    /// Every opener still waiting to be closed, innermost last
+    stack: Vec<Unclosed<'g>>,
+    /// Whether the innermost opener is a quote, so only its closer counts
+    in_quote: bool,
+    /// The escape character of the quote we're in, if it has one
+    quote_escape: Option<char>,
+    at: Position,
+    /// Text we can't make sense of yet, as it might be the start of a longer delimiter
+    pending: String,
+    /// Once we've found corruption, that's that
+    corrupted: Option<Diagnostic<'g>>,
+    /// Every character a delimiter can start with, so we can skip past everything else quickly
+    first_chars: Vec<char>,
+    /// The last character we made sense of, to tell whether a delimiter starts a word
+    previous: Option<char>,
+}
+
+impl<'g> Validator<'g> {
+    pub fn new(grammar: &'g Grammar) -> Self {
+        Validator {
+            grammar,
+            stack: Vec::with_capacity(128),
+            in_quote: false,
+            quote_escape: None,
+            at: Position { line: 1, column: 1 },
+            pending: String::new(),
+            corrupted: None,
+            first_chars: grammar.delimiters()
+                .filter_map(|delimiter| delimiter.chars().next())
+                .collect(),
+            previous: None,
+        }
+    }
+
+    /// Take the next piece of text.  It can be split anywhere, even in the middle of a
+    /// delimiter.
+    pub fn feed(&mut self, chunk: &str) {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.push_str(chunk);
+        let used = self.consume(&pending, false);
+        pending.drain(..used);
+        self.pending = pending;
+    }
+
+    pub fn feed_char(&mut self, c: char) {
+        self.feed(c.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Feed in everything a reader has, a block at a time.
+    pub fn feed_reader<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut buffer = vec![0u8; 1 << 16];
+        // The start of a character that got split between reads
+        let mut carried = 0;
+        loop {
+            let read = match reader.read(&mut buffer[carried..]) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            let filled = carried + read;
+            let valid = match std::str::from_utf8(&buffer[..filled]) {
+                Ok(text) => text.len(),
+                Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            };
+            self.feed(std::str::from_utf8(&buffer[..valid]).unwrap());
+            buffer.copy_within(valid..filled, 0);
+            carried = filled - valid;
+        }
+        if carried > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "text ends partway through a character"));
+        }
+        Ok(())
+    }
+
+    /// How things stand if the text ended here - None if it's all balanced, otherwise what's
+    /// wrong with it.  Everything fed in so far counts, even what's still pending.
+    pub fn status(&self) -> Option<Diagnostic<'g>> {
+        let mut finished = self.clone();
+        let pending = std::mem::take(&mut finished.pending);
+        finished.consume(&pending, true);
+        if finished.corrupted.is_some() { return finished.corrupted; }
+        // A line comment's fine without a newline at the very end
+        if finished.in_quote && finished.stack.last().map(|u| u.expected) == Some("\n") { finished.stack.pop(); }
+        if finished.stack.is_empty() { None } else { Some(Diagnostic::Incomplete { unclosed: finished.stack }) }
+    }
+
+    /// The closers we'd need to finish off what we've seen so far, next one last.
+    pub fn expected(&self) -> Vec<&'g str> {
+        self.stack.iter().map(|u| u.expected).collect()
+    }
+
+    // Make sense of as much of `text` as we can, returning how many bytes of it we used.  Unless
+    // it's the end, we stop before anything that could turn out to be part of a longer delimiter.
+    fn consume(&mut self, text: &str, at_end: bool) -> usize {
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if self.corrupted.is_some() { return text.len(); }
+            let mut step = c.len_utf8();
+            if self.in_quote {
+                let closer = self.stack.last().unwrap().expected;
+                if Some(c) == self.quote_escape {
+                    match rest[step..].chars().next() {
+                        Some(escaped) => step += escaped.len_utf8(),
+                        None if !at_end => break,
+                        None => { },
+                    }
+                } else if rest.starts_with(closer) {
+                    self.stack.pop();
+                    self.in_quote = false;
+                    step = closer.len();
+                } else if !at_end && closer.len() > rest.len() && closer.starts_with(rest) {
+                    break;
+                }
+            } else {
+                // Only bother looking for a delimiter if one could start here
+                let could_start = self.first_chars.contains(&c);
+                if could_start && !at_end && self.grammar.could_be_longer(rest) { break; }
+                let token = if could_start { self.grammar.token_at(self.previous, rest, self.stack.last().map(|u| u.expected)) } else { None };
+                let at = self.at;
+                match token {
+                    // For every opener, push the corresponding closer onto the stack
+                    Some((Token::Open(open, expected), length)) => {
+                        self.stack.push(Unclosed { open, expected, at });
+                        step = length;
+                    },
+                    Some((Token::Quote(open, expected, escape), length)) => {
+                        self.stack.push(Unclosed { open, expected, at });
+                        self.in_quote = true;
+                        self.quote_escape = escape;
+                        step = length;
+                    },
+                    // Make sure closers match the stack, or we have corruption
+                    Some((Token::Close(close), length)) => {
+                        let innermost = self.stack.pop();
+                        if innermost.map(|u| u.expected) != Some(close) {
+                            self.corrupted = Some(Diagnostic::Corrupted { found: Found { text: close.to_string(), at }, unclosed: innermost });
+                        }
+                        step = length;
+                    },
+                    None if !self.grammar.ignore_others && !self.grammar.ignorable.contains(&c) => {
+                        self.corrupted = Some(Diagnostic::Corrupted { found: Found { text: c.to_string(), at }, unclosed: self.stack.pop() });
+                    },
+                    None => { },
+                }
             }
+            for consumed in rest[..step].chars() {
+                if consumed == '\n' { self.at = Position { line: self.at.line + 1, column: 1 }; } else { self.at.column += 1; }
+                self.previous = Some(consumed);
+            }
+            rest = &rest[step..];
         }
+        text.len() - rest.len()
     }
-    Ok(expected)
+}
+
+impl Diagnostic<'_> {
+    /// The lines a rendering of this quotes from.
+    pub fn lines(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = match self {
+            Diagnostic::Corrupted { found, unclosed } => unclosed.iter().map(|u| u.at.line).chain([found.at.line]).collect(),
+            Diagnostic::Incomplete { unclosed } => unclosed.iter().map(|u| u.at.line).collect(),
+        };
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Diagnostic::Corrupted { found, unclosed: Some(unclosed) } =>
+                format!("expected '{}', found '{}'", escape(unclosed.expected), escape(&found.text)),
+            Diagnostic::Corrupted { found, unclosed: None } => format!("found '{}' with nothing open", escape(&found.text)),
+            Diagnostic::Incomplete { unclosed } if unclosed.len() == 1 => "1 delimiter left open".to_string(),
+            Diagnostic::Incomplete { unclosed } => format!("{} delimiters left open", unclosed.len()),
+        }
+    }
+
+    /// Draw the problem the way a compiler would, quoting the lines involved with a mark under
+    /// each delimiter that matters - a caret for what's wrong, and dashes for the openers.
+    pub fn render(&self, text: &str) -> String {
+        let source_lines: Vec<&str> = text.lines().collect();
+        self.render_with(|line| source_lines.get(line - 1).copied().unwrap_or("").to_string())
+    }
+
+    /// Render, getting each line of text (counting from 1) from `source_line` - so we only
+    /// need the lines in lines(), and not the whole text.
+    pub fn render_with<F: FnMut(usize) -> String>(&self, mut source_line: F) -> String {
+        let mut labels: Vec<(Position, usize, char, String)> = Vec::new();
+        let mut open_label = |u: &Unclosed, what: &str| {
+            labels.push((u.at, u.open.chars().count(), '-', format!("'{}' {}, expecting '{}'", escape(u.open), what, escape(u.expected))));
+        };
+        let kind = match self {
+            Diagnostic::Corrupted { found, unclosed } => {
+                if let Some(unclosed) = unclosed { open_label(unclosed, "opened here"); }
+                labels.push((found.at, found.text.chars().count(), '^', format!("found '{}'", escape(&found.text))));
+                "corrupted"
+            },
+            Diagnostic::Incomplete { unclosed } => {
+                unclosed.iter().for_each(|u| open_label(u, "never closed"));
+                "incomplete"
+            },
+        };
+        labels.sort_by_key(|label| label.0);
+
+        let main_at = if let Diagnostic::Corrupted { found, .. } = self { found.at } else { labels[0].0 };
+        let gutter = labels.iter().map(|label| label.0.line).max().unwrap_or(1).to_string().len();
+        let mut rendered = format!("{}: {}\n{:gutter$}--> {}:{}\n{:gutter$} |\n", kind, self.message(), "", main_at.line, main_at.column, "", gutter = gutter);
+        let (mut last_line, mut text) = (0, String::new());
+        for (at, width, mark, label) in labels {
+            if at.line != last_line {
+                text = source_line(at.line);
+                rendered += &format!("{:>gutter$} | {}\n", at.line, text, gutter = gutter);
+                last_line = at.line;
+            }
+            // Keep any tabs before the mark, so it lines up however wide the terminal draws them
+            let padding: String = text.chars().chain(std::iter::repeat(' ')).take(at.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            rendered += &format!("{:gutter$} | {}{} {}\n", "", padding, mark.to_string().repeat(width.max(1)), label, gutter = gutter);
+        }
+        rendered
+    }
+}
+
+// Show newlines and tabs so they don't mess up a rendered diagnostic
+fn escape(text: &str) -> String {
+    text.escape_default().to_string()
+}
+
+/// One change to a line, at a position (in chars) in the original line.  Insertions go just
+/// before the character that's there, or at the end if it's the line's length.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Edit {
+    Insert(usize, char),
+    Delete(usize, char),
+    Substitute(usize, char, char),
+}
+
+impl std::fmt::Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Edit::Insert(at, c) => write!(f, "insert '{}' at {}", c, at),
+            Edit::Delete(at, c) => write!(f, "delete '{}' at {}", c, at),
+            Edit::Substitute(at, from, to) => write!(f, "replace '{}' with '{}' at {}", from, to, at),
+        }
+    }
+}
+
+/// A balanced version of a line, and the edits that got us there.
+#[derive(Debug, PartialEq, Eq)]
+struct Repair {
+    line: String,
+    edits: Vec<Edit>,
+}
+
+// What it costs to make a and b (in that order) into a matching pair, and what they become
+fn pair_cost(a: char, b: char) -> (u32, char, char) {
+    let open = OPEN.chars().position(|c| c == a);
+    let close = CLOSE.chars().position(|c| c == b);
+    match (open, close) {
+        (Some(x), Some(y)) if x == y => (0, a, b),
+        (Some(x), _) => (1, a, CLOSE.chars().nth(x).unwrap()),
+        (None, Some(y)) => (1, OPEN.chars().nth(y).unwrap(), b),
+        (None, None) => (2, '(', ')'),
+    }
+}
+
+/// The longest line `repair` will take on.
+const MAX_REPAIR_LENGTH: usize = 1000;
+
+/// A line too long for `repair`, and how long it was.
+#[derive(Debug, PartialEq, Eq)]
+struct TooLong(usize);
+
+impl std::fmt::Display for TooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} characters is too long to repair, the limit is {}", self.0, MAX_REPAIR_LENGTH)
+    }
+}
+
+// Find the fewest insertions, deletions and substitutions that make a line balanced.  cost[i][j]
+// is the fewest edits to balance chars i..j, which is either pairing char i with some later
+// char k (fixing them up if they don't already match) and balancing what's inside and after
+// separately, or dealing with char i on its own - an opener gets a closer inserted at the end of
+// the span, anything else is deleted.  That's O(n^3) time and O(n^2) memory, so we turn away
+// anything longer than MAX_REPAIR_LENGTH rather than grind through it.
+fn repair(line: &str) -> Result<Repair, TooLong> {
+    let chars: Vec<char> = line.chars().collect();
+    let n = chars.len();
+    if n > MAX_REPAIR_LENGTH { return Err(TooLong(n)); }
+    let mut cost = vec![vec![0u32; n + 1]; n + 1];
+    // Which k char i pairs with for each span, or None if it's on its own
+    let mut partner: Vec<Vec<Option<usize>>> = vec![vec![None; n + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in i + 1..=n {
+            cost[i][j] = cost[i + 1][j] + 1;
+            for k in i + 1..j {
+                let paired = pair_cost(chars[i], chars[k]).0 + cost[i + 1][k] + cost[k + 1][j];
+                if paired < cost[i][j] || (paired == cost[i][j] && partner[i][j].is_none()) {
+                    cost[i][j] = paired;
+                    partner[i][j] = Some(k);
+                }
+            }
+        }
+    }
+
+    // Then walk back through the choices, writing the line out in order
+    fn rebuild(chars: &[char], partner: &[Vec<Option<usize>>], i: usize, j: usize, repair: &mut Repair) {
+        if i >= j { return; }
+        match partner[i][j] {
+            Some(k) => {
+                let (_, open, close) = pair_cost(chars[i], chars[k]);
+                if open != chars[i] { repair.edits.push(Edit::Substitute(i, chars[i], open)); }
+                repair.line.push(open);
+                rebuild(chars, partner, i + 1, k, repair);
+                if close != chars[k] { repair.edits.push(Edit::Substitute(k, chars[k], close)); }
+                repair.line.push(close);
+                rebuild(chars, partner, k + 1, j, repair);
+            },
+            None => match OPEN.chars().position(|c| c == chars[i]) {
+                Some(x) => {
+                    repair.line.push(chars[i]);
+                    rebuild(chars, partner, i + 1, j, repair);
+                    let close = CLOSE.chars().nth(x).unwrap();
+                    repair.edits.push(Edit::Insert(j, close));
+                    repair.line.push(close);
+                },
+                None => {
+                    repair.edits.push(Edit::Delete(i, chars[i]));
+                    rebuild(chars, partner, i + 1, j, repair);
+                },
+            },
+        }
+    }
+    let mut fixed = Repair { line: String::with_capacity(n), edits: Vec::new() };
+    rebuild(&chars, &partner, 0, n, &mut fixed);
+    Ok(fixed)
 }
 
 // Compute answer to part 1, and simultaneously get completion scores for part 2
@@ -58,12 +561,50 @@ fn compute_scores(lines: Vec<String>) -> (u64, Vec<u64>) {
 
 
 fn main() -> io::Result<()> {
+    // Usage: day10              - score input.txt
+    //        day10 repair       - print the smallest fix for each broken line of input.txt
+    //        day10 check FILE   - check the delimiters in a source file balance
+    let args : Vec<String> = std::env::args().collect();
+    if args.get(1).map(|x| x.as_str()) == Some("check") {
+        let filename = args.get(2).expect("Expected a file to check");
+        let grammar = Grammar::source_code();
+        let mut validator = Validator::new(&grammar);
+        validator.feed_reader(File::open(filename)?)?;
+        match validator.status() {
+            None => println!("{}: balanced", filename),
+            Some(diagnostic) => {
+                // Go back for just the lines we need to show
+                let wanted = diagnostic.lines();
+                let mut source_lines = std::collections::HashMap::new();
+                for (number, line) in BufReader::new(File::open(filename)?).lines().enumerate() {
+                    if number + 1 > *wanted.last().unwrap() { break; }
+                    if wanted.contains(&(number + 1)) { source_lines.insert(number + 1, line?); }
+                }
+                print!("{}: {}", filename, diagnostic.render_with(|line| source_lines.get(&line).cloned().unwrap_or_default()));
+            },
+        }
+        return Ok(());
+    }
+    let repair_mode = args.get(1).map(|x| x.as_str()) == Some("repair");
     let file = File::open("input.txt")
         .unwrap_or_else(|_| panic!("File 'input.txt' not readable.") );
     let reader = BufReader::new(file);
     let lines = reader.lines()
         .map(|x| x.unwrap_or_else(|err| panic!("IO Error with input.txt: {}", err)))
-        .collect();
+        .collect::<Vec<String>>();
+
+    if repair_mode {
+        for (line_number, line) in lines.iter().enumerate() {
+            let fixed = match repair(line.trim()) {
+                Ok(fixed) => fixed,
+                Err(err) => { eprintln!("Line {}: {}", line_number + 1, err); continue; }
+            };
+            if fixed.edits.is_empty() { continue; }
+            let edits: Vec<String> = fixed.edits.iter().map(|edit| edit.to_string()).collect();
+            println!("Line {}: {} ({})", line_number + 1, fixed.line, edits.join(", "));
+        }
+        return Ok(());
+    }
 
     let (error_score, mut incomplete_scores) = compute_scores(lines);
 
@@ -95,4 +636,175 @@ fn test_examples() {
     let (error_score, incomplete_scores) = compute_scores(lines);
     assert_eq!(error_score, 26397);
     assert_eq!(incomplete_scores, vec![288957, 5566, 1480781, 995444, 294]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_repair() {
+    // Already balanced lines are left alone
+    assert_eq!(repair("[<>({}){}[([])<>]]").unwrap(), Repair { line: "[<>({}){}[([])<>]]".to_string(), edits: vec![] });
+
+    // Incomplete lines can be closed off at the end, but it's often cheaper to turn some of
+    // the openers round - this needs eight closers, or four substitutions
+    let fixed = repair("[({(<(())[]>[[{[]{<()<>>").unwrap();
+    assert_eq!(fixed.edits.len(), 4);
+    assert_eq!(validate(&fixed.line), Ok(String::new()));
+    assert_eq!(repair("{<>").unwrap(), Repair { line: "{<>}".to_string(), edits: vec![Edit::Insert(3, '}')] });
+
+    // A wrong closer is swapped for the right one
+    let fixed = repair("{([(<{}[<>[]}>{[]{[(<()>").unwrap();
+    assert_eq!(fixed.edits.len(), 5);
+    assert_eq!(validate(&fixed.line), Ok(String::new()));
+
+    let fixed = repair("(]").unwrap();
+    assert_eq!(fixed, Repair { line: "()".to_string(), edits: vec![Edit::Substitute(1, ']', ')')] });
+
+    // A stray closer is cheapest to just delete
+    assert_eq!(repair("())").unwrap(), Repair { line: "()".to_string(), edits: vec![Edit::Delete(2, ')')] });
+    assert_eq!(repair(")(").unwrap(), Repair { line: "()".to_string(), edits: vec![Edit::Substitute(0, ')', '('), Edit::Substitute(1, '(', ')')] });
+    assert_eq!(Edit::Substitute(3, ']', '>').to_string(), "replace ']' with '>' at 3");
+
+    // Lines past the limit are turned away rather than taking forever
+    assert_eq!(repair(&"(".repeat(MAX_REPAIR_LENGTH + 1)), Err(TooLong(MAX_REPAIR_LENGTH + 1)));
+}
+
+#[test]
+fn test_grammar() {
+    // Ignorable characters are skipped, but anything else still counts as corruption
+    let spaced = Grammar::brackets().with_ignorable(" ");
+    assert_eq!(validate_with(&spaced, "( [ ] <"), Ok(vec![")", ">"]));
+    assert_eq!(validate_with(&spaced, "( x )"), Err("x".to_string()));
+
+    // Multi-character delimiters, with the longest match winning
+    let words = Grammar::default().with_pair("begin", "end").with_pair("(", ")").with_pair("(*", "*)").with_ignorable(" ;");
+    assert_eq!(validate_with(&words, "begin (* ( ) *) ; ( ) end"), Ok(vec![]));
+    assert_eq!(validate_with(&words, "begin ( end )"), Err("end".to_string()));
+    assert_eq!(validate_with(&words, "begin (*"), Ok(vec!["end", "*)"]));
+
+    // Word-like delimiters only count as whole words
+    let program = Grammar::source_code().with_pair("begin", "end");
+    assert_eq!(validate_with(&program, "begin beginning; endless end"), Ok(vec![]));
+    assert_eq!(validate_with(&program, "begin rebegin_2 end"), Ok(vec![]));
+    assert_eq!(validate_with(&program, "begin(end)"), Err("end".to_string()));
+    assert_eq!(validate_with(&program, "friend"), Ok(vec![]));
+
+    // The same delimiter can open and close
+    let bars = Grammar::default().with_pair("|", "|").with_pair("(", ")");
+    assert_eq!(validate_with(&bars, "|(||)|"), Ok(vec![]));
+    assert_eq!(validate_with(&bars, "|(|)"), Err(")".to_string()));
+
+    // Delimiters in strings and comments don't count, escapes included
+    let source = Grammar::source_code();
+    assert_eq!(validate_with(&source, "fn f() { let s = \"}\\\"(\"; } /* { */ // ]\n"), Ok(vec![]));
+    assert_eq!(validate_with(&source, "if (x == ')') { y[0]; }"), Ok(vec![]));
+    assert_eq!(validate_with(&source, "f(\"unfinished"), Ok(vec![")", "\""]));
+    assert_eq!(validate_with(&source, "a[b)]"), Err(")".to_string()));
+    assert_eq!(validate_with(&source, "{} // no newline"), Ok(vec![]));
+
+    // Only strings and characters have escapes, so a backslash can end a comment
+    assert_eq!(validate_with(&source, "/* \\*/ ( )"), Ok(vec![]));
+    assert_eq!(validate_with(&source, "( // C:\\\n)"), Ok(vec![]));
+}
+
+#[test]
+fn test_diagnostics() {
+    let brackets = Grammar::brackets();
+    let text = "{([(<{}[<>[]}>{[]{[(<()>";
+    let diagnostic = diagnose(&brackets, text).unwrap();
+    assert_eq!(diagnostic, Diagnostic::Corrupted {
+        found: Found { text: "}".to_string(), at: Position { line: 1, column: 13 } },
+        unclosed: Some(Unclosed { open: "[", expected: "]", at: Position { line: 1, column: 8 } }),
+    });
+    assert_eq!(diagnostic.render(text), [
+        "corrupted: expected ']', found '}'",
+        " --> 1:13",
+        "  |",
+        "1 | {([(<{}[<>[]}>{[]{[(<()>",
+        "  |        - '[' opened here, expecting ']'",
+        "  |             ^ found '}'",
+        "",
+    ].join("\n"));
+
+    // Every unclosed opener is listed, across lines
+    let text = "(\n  [<>\n";
+    let spaced = Grammar::brackets().with_ignorable(" \n");
+    let diagnostic = diagnose(&spaced, text).unwrap();
+    assert_eq!(diagnostic.render(text), [
+        "incomplete: 2 delimiters left open",
+        " --> 1:1",
+        "  |",
+        "1 | (",
+        "  | - '(' never closed, expecting ')'",
+        "2 |   [<>",
+        "  |   - '[' never closed, expecting ']'",
+        "",
+    ].join("\n"));
+
+    // Closing with nothing open, and multi-character delimiters
+    assert_eq!(diagnose(&brackets, "()>").unwrap().message(), "found '>' with nothing open");
+    let comments = Grammar::default().with_pair("/*", "*/").with_pair("(", ")");
+    let text = "/*(*/";
+    let diagnostic = diagnose(&comments, text).unwrap();
+    assert!(diagnostic.render(text).ends_with("1 | /*(*/\n  |   - '(' opened here, expecting ')'\n  |    ^^ found '*/'\n"));
+    assert_eq!(diagnose(&comments, "/**/()"), None);
+
+    // Tabs before a mark are kept, so it still lines up
+    let tabbed = Grammar::brackets().with_ignorable("\t ");
+    let text = "\t( \t]";
+    assert!(diagnose(&tabbed, text).unwrap().render(text).ends_with("1 | \t( \t]\n  | \t- '(' opened here, expecting ')'\n  | \t  \t^ found ']'\n"));
+}
+
+#[test]
+fn test_validator() {
+    let source = Grammar::source_code().with_pair("begin", "end");
+    let texts = [
+        "begin f(\"a\\\"b)\"); /* ] */ end",
+        "begin x[0] = '\\'' // ) \n",
+        "begin /* never ends",
+        "begin ( ] end",
+        "be",
+        "begin beginning endless end",
+        "begin(x) end",
+        "/* C:\\*/ \"\\\"\" ( ]",
+    ];
+    for text in texts {
+        let whole = diagnose(&source, text);
+        // However it's split up, we get the same answer as all at once
+        for split in (0..=text.len()).filter(|&i| text.is_char_boundary(i)) {
+            let mut validator = Validator::new(&source);
+            validator.feed(&text[..split]);
+            validator.feed(&text[split..]);
+            assert_eq!(validator.status(), whole, "{:?} split at {}", text, split);
+        }
+        let mut validator = Validator::new(&source);
+        text.chars().for_each(|c| validator.feed_char(c));
+        assert_eq!(validator.status(), whole);
+    }
+
+    // Status along the way, and snapshots to go back to
+    let brackets = Grammar::brackets();
+    let mut validator = Validator::new(&brackets);
+    validator.feed("[(");
+    assert_eq!(validator.expected(), vec!["]", ")"]);
+    let snapshot = validator.clone();
+    validator.feed(")]");
+    assert_eq!(validator.status(), None);
+    validator.feed(">");
+    assert!(matches!(validator.status(), Some(Diagnostic::Corrupted { .. })));
+    assert!(matches!(snapshot.status(), Some(Diagnostic::Incomplete { .. })));
+
+    // Reading a byte at a time splits characters up, which has to work too
+    struct OneByte<'a>(&'a [u8]);
+    impl Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() { return Ok(0); }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+    let text = "(é[\"→)\"]) ⟨";
+    let mut validator = Validator::new(&source);
+    validator.feed_reader(OneByte(text.as_bytes())).unwrap();
+    assert_eq!(validator.status(), diagnose(&source, text));
+    assert!(Validator::new(&source).feed_reader(OneByte(&[b'(', 0xff])).is_err());
+}